@@ -1,40 +1,73 @@
+use std::cell::{RefCell};
 use std::collections::{HashSet};
-use std::fs::{remove_dir_all, remove_file, rename, File};
-use std::io::{self, Read, Write};
+use std::ffi::{OsStr};
+use std::fmt;
+use std::fs::{create_dir, metadata, remove_dir, remove_file, rename, symlink_metadata, File, Metadata};
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::ffi::{OsStrExt};
+use std::os::unix::fs::{MetadataExt};
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
 use std::process::{Command};
 use std::result;
 
 use eventual::{Async, Future};
-use glob::{MatchOptions, Pattern};
+use time;
 
-use config::{Config, Only};
+use attr;
+use config::{Action, Config, OnError, Only, Report};
+use copy::{self, copy_over, create_file_over, hard_link_over, move_entry, symlink_over};
 use entry::{Entry, EntryMap};
 use error::{Error};
+use expr::{Expr};
+use filter::{Filter};
 use formatter::{Formatter};
+use history::{self, History, Session};
+use ignore::{self, Ignore};
+use journal::{Journal};
 use parser::{Parser, Transform};
-use util::{width};
+use plan::{temp_path, Layout, Operation, Plan, Problem};
+use report::{self, Record, Status};
+use review;
+use script;
+use sort::{Order};
+#[cfg(feature = "trash")]
+use trash;
+use util::{self, width};
 
-#[derive(Debug)]
 pub struct App {
-    config: Config
+    config: Config,
+    answers: Option<RefCell<Box<BufRead>>>
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
-const MATCH_OPTIONS: MatchOptions = MatchOptions {
-    case_sensitive: true,
-    require_literal_separator: false,
-    require_literal_leading_dot: false
-};
+// Progress messages and prompts go to stderr while stdout is taken by the report
+macro_rules! say {
+    ($app:expr, $($arg:tt)*) => { $app.say(format_args!($($arg)*)) };
+}
 
 impl App {
     pub fn new(config: Config) -> Self {
         App {
-            config: config
+            config: config,
+            answers: None
         }
     }
 
+    // Answers to questions are read from `input' instead of stdin
+    pub fn answers(&mut self, input: Box<BufRead>) {
+        self.answers = Some(RefCell::new(input));
+    }
+
     pub fn run(&mut self) -> Result<()> {
+        match self.config.action {
+            Action::Edit => self.edit(),
+            Action::Undo(ref session) => self.undo(session.as_ref().map(|s| &s[..])),
+            Action::History => self.list_sessions()
+        }
+    }
+
+    fn edit(&mut self) -> Result<()> {
         let mut entries = try!(self.list_entries());
 
         // Abort if nothing to do
@@ -44,7 +77,7 @@ impl App {
             return Ok(());
         }
 
-        entries.sort();
+        self.arrange(&mut entries);
         try!(self.write_transforms(&entries));
 
         let future = Future::spawn(move || EntryMap::from(entries));
@@ -53,83 +86,338 @@ impl App {
 
         let entries = try!(future.await());
 
-        let transforms = try!(self.read_transforms());
-        try!(self.apply_transforms(&entries, &transforms));
+        loop {
+            if self.config.review {
+                let planned = self.read_transforms().and_then(|transforms| self.plan_transforms(&entries, &transforms));
+
+                let choice = match planned {
+                    Ok(plan) => try!(self.review(&plan.operations())),
+                    Err(e) => try!(self.review_problems(e))
+                };
+
+                match choice {
+                    Choice::Apply => {
+                        // The plan is confirmed as a whole, don't ask about each operation again
+                        if self.config.default_answer.is_none() {
+                            self.config.default_answer = Some(true);
+                        }
+                    },
+                    Choice::Edit => {
+                        try!(self.edit_transforms());
+                        continue;
+                    },
+                    Choice::Quit => {
+                        say!(self, "nothing has been changed");
+                        break;
+                    }
+                }
+            }
+
+            let transforms = try!(self.read_transforms());
+            try!(self.apply_transforms(&entries, &transforms));
+            break;
+        }
 
         try!(remove_file(&self.config.transforms_path));
 
         Ok(())
     }
 
-    fn matches(&self, entry: &Entry) -> Result<bool> {
-        if let Some(ref globs) = self.config.globs {
-            for ref glob in globs {
-                let pattern = try!(Pattern::new(glob));
+    fn undo(&self, id: Option<&str>) -> Result<()> {
+        let history = History::new(self.config.history_path());
+        let session = try!(history::select(try!(history.sessions()), id, &self.config.dir));
+        let operations = try!(session.undo_operations());
 
-                if pattern.matches_with(entry.basename(), &MATCH_OPTIONS) {
-                    return Ok(true);
-                }
+        say!(self, "undoing session {} in {}", session.id(), quote_dirs(session.dirs()));
+
+        for operation in session.operations() {
+            if let Operation::Remove { ref path } = *operation {
+                say!(self, "cannot restore removed `{}'", path.display());
             }
+        }
+
+        let mut state = State::new();
+        let mut result = Ok(());
+
+        for operation in &operations {
+            if let Err(e) = self.apply_recorded(operation, &mut state) {
+                result = Err(e);
+                break;
+            }
+        }
+
+        try!(self.write_report(&operations, &mut state));
+        try!(result);
 
-            return Ok(false);
+        if !self.config.dry_run {
+            try!(history.forget(&session));
         }
 
-        Ok(true)
+        Ok(())
+    }
+
+    fn list_sessions(&self) -> Result<()> {
+        let history = History::new(self.config.history_path());
+
+        for session in try!(history.sessions()) {
+            let dirs: Vec<_> = session.dirs().iter().map(|dir| dir.to_string_lossy().into_owned()).collect();
+
+            println!("{} {} {} ({} operations)",
+                     session.id(),
+                     history::format_time(session.started()),
+                     dirs.join(" "),
+                     session.operations().len());
+        }
+
+        Ok(())
+    }
+
+    fn filter(&self) -> Result<Filter> {
+        let mut filter = Filter::new();
+        filter.ignore_case(self.config.ignore_case);
+
+        for glob in self.config.globs.iter().flat_map(|globs| globs) {
+            try!(filter.include_glob(glob));
+        }
+
+        for regex in &self.config.regexes {
+            try!(filter.include_regex(regex));
+        }
+
+        for glob in &self.config.excludes {
+            try!(filter.exclude_glob(glob));
+        }
+
+        for regex in &self.config.exclude_regexes {
+            try!(filter.exclude_regex(regex));
+        }
+
+        Ok(filter)
+    }
+
+    fn order(&self) -> Order {
+        let mut order = Order::new(self.config.sort);
+        order.reverse(self.config.reverse);
+        order.dirs_first(self.config.dirs_first);
+        order.hidden_first(self.config.hidden_first);
+        order
+    }
+
+    fn listing(&self) -> Result<Listing> {
+        Ok(Listing {
+            entries: Vec::new(),
+            hashes: HashSet::new(),
+            paths: HashSet::new(),
+            ancestors: Vec::new(),
+            ignores: Vec::new(),
+            filter: try!(self.filter()),
+            condition: match self.config.condition {
+                Some(ref condition) => Some(try!(Expr::parse(condition))),
+                None => None
+            },
+            now: time::get_time().sec
+        })
+    }
+
+    // Entries are sorted and grouped by the directory they are listed in, in the order of the arguments
+    fn arrange(&self, entries: &mut Vec<Entry>) {
+        self.order().sort(entries);
+
+        let dirs = self.config.dirs();
+        entries.sort_by_key(|entry| dirs.iter().position(|&dir| dir == entry.root()));
     }
 
     fn list_entries(&mut self) -> Result<Vec<Entry>> {
-        let entries = try!(self.config.dir.read_dir());
-        let mut result = Vec::new();
+        let mut listing = try!(self.listing());
 
-        let mut set: HashSet<String> = HashSet::new();
+        if self.config.stdin {
+            let stdin = io::stdin();
+            try!(self.read_entries(&mut stdin.lock(), &mut listing));
+            return Ok(listing.entries);
+        }
 
-        for entry in entries {
-            let entry = Entry::new(try!(entry).path());
+        let dirs: Vec<PathBuf> = self.config.dirs().into_iter().map(Path::to_path_buf).collect();
 
-            if !self.config.show_hidden && entry.is_hidden() {
-                continue;
+        for dir in &dirs {
+            // The global ignore file applies to each of the directories
+            if self.config.ignore {
+                if let Some(ref path) = self.config.global_ignore_path {
+                    listing.ignores.extend(try!(Ignore::load(dir, path)));
+                }
             }
 
-            if let Some(ref only) = self.config.only {
-                if entry.is_dir() && only == &Only::Files {
+            try!(self.list_dir(dir, dir, 1, &mut listing));
+            listing.ignores.clear();
+        }
+
+        Ok(listing.entries)
+    }
+
+    // Paths separated by newlines, or by NUL characters if there are any (as `find -print0' does).
+    // Relative paths are taken from the working directory and named by their path relative
+    // to it, so each entry stays in its own parent directory.
+    fn read_entries<R: Read>(&mut self, input: &mut R, listing: &mut Listing) -> Result<()> {
+        let mut paths = Vec::new();
+        try!(input.read_to_end(&mut paths));
+
+        // Paths are taken as they are, they don't need to be valid UTF-8
+        let separator = if paths.contains(&b'\0') { b'\0' } else { b'\n' };
+
+        for path in paths.split(|&byte| byte == separator).filter(|path| !path.is_empty()) {
+            let path = Path::new(OsStr::from_bytes(path));
+
+            // `./' components are dropped, as `find' puts them in front of every path
+            let entry = Entry::relative(&self.config.dir, self.config.dir.join(path).components().collect());
+            let entry = if self.config.follow { entry.follow() } else { entry };
+
+            let metadata = match self.entry_metadata(&entry) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    say!(self, "skipping `{}': {}", path.display(), e);
                     continue;
                 }
+            };
 
-                if entry.is_file() && only == &Only::Dirs {
-                    continue
-                }
+            if self.listed(&entry, &metadata, listing) {
+                self.add_entry(entry, listing);
             }
+        }
 
-            if !try!(self.matches(&entry)) {
+        Ok(())
+    }
+
+    // Entries of `dir', which is `depth' - 1 levels below `root', the directory they are named relative to
+    fn list_dir(&mut self, root: &Path, dir: &Path, depth: usize, listing: &mut Listing) -> Result<()> {
+        let loaded = if self.config.ignore {
+            let found = try!(Ignore::load_dir(dir));
+            let count = found.len();
+            listing.ignores.extend(found);
+            count
+        } else {
+            0
+        };
+
+        let dir_metadata = try!(metadata(dir));
+        listing.ancestors.push((dir_metadata.dev(), dir_metadata.ino()));
+
+        for entry in try!(dir.read_dir()) {
+            let entry = Entry::relative(root, try!(entry).path());
+            let entry = if self.config.follow { entry.follow() } else { entry };
+
+            if !self.config.show_hidden && entry.is_hidden() {
                 continue;
             }
 
-            {
-                let mut hash = entry.hash_short(self.config.hash_width);
+            let metadata = try!(self.entry_metadata(&entry));
+            let is_dir = metadata.is_dir();
 
-                if set.contains(hash) {
-                    self.config.hash_width += 1;
-                    hash = entry.hash_short(self.config.hash_width);
+            if ignore::is_ignored(&listing.ignores, entry.path(), is_dir) {
+                continue;
+            }
+
+            if is_dir && self.config.max_depth.map_or(true, |max| depth < max) {
+                // A followed link may lead back to one of the directories being listed
+                if listing.ancestors.contains(&(metadata.dev(), metadata.ino())) {
+                    say!(self, "not descending into `{}', which leads back to its parent", entry.name());
+                } else {
+                    try!(self.list_dir(root, entry.path(), depth + 1, listing));
                 }
+            }
 
-                set.insert(String::from(hash));
+            if depth < self.config.min_depth || !self.listed(&entry, &metadata, listing) {
+                continue;
             }
 
-            result.push(entry);
+            self.add_entry(entry, listing);
         }
 
-        Ok(result)
+        let len = listing.ignores.len();
+        listing.ignores.truncate(len - loaded);
+        listing.ancestors.pop();
+
+        Ok(())
+    }
+
+    // Symbolic links are only followed with `--follow', broken ones never are
+    fn entry_metadata(&self, entry: &Entry) -> io::Result<Metadata> {
+        if self.config.follow {
+            metadata(entry.path()).or_else(|_| symlink_metadata(entry.path()))
+        } else {
+            symlink_metadata(entry.path())
+        }
+    }
+
+    // Short hashes are widened until they are unique, entries listed before are left out
+    fn add_entry(&mut self, entry: Entry, listing: &mut Listing) {
+        if !listing.paths.insert(entry.path().to_path_buf()) {
+            return;
+        }
+
+        {
+            let mut hash = entry.hash_short(self.config.hash_width);
+
+            if listing.hashes.contains(hash) {
+                self.config.hash_width += 1;
+                hash = entry.hash_short(self.config.hash_width);
+            }
+
+            listing.hashes.insert(String::from(hash));
+        }
+
+        listing.entries.push(entry);
+    }
+
+    fn listed(&self, entry: &Entry, metadata: &Metadata, listing: &Listing) -> bool {
+        // Anything but a directory is a file, symbolic links included
+        if let Some(ref only) = self.config.only {
+            if entry.is_dir() != (only == &Only::Dirs) {
+                return false;
+            }
+        }
+
+        if let Some(ref condition) = listing.condition {
+            if !condition.matches(entry.path(), metadata, listing.now) {
+                return false;
+            }
+        }
+
+        listing.filter.is_listed(entry.name(), entry.basename())
     }
 
     fn write_transforms(&self, entries: &[Entry]) -> Result<()> {
         let path = &self.config.transforms_path;
         let mut file = try!(File::create(path));
 
-        try!(writeln!(file, "# Edit directory {}\n", self.config.dir.display()));
+        let several = !self.config.extra_dirs.is_empty();
+
+        if self.config.stdin {
+            try!(writeln!(file, "# Edit paths read from stdin, relative to {}\n", self.config.dir.display()));
+        } else if several {
+            try!(writeln!(file, "# Edit directories, names are relative to the one they are listed under\n"));
+        } else {
+            try!(writeln!(file, "# Edit directory {}\n", self.config.dir.display()));
+        }
+
+        if self.config.long {
+            try!(writeln!(file, "# Columns: hash, mode, owner, group, modification time, name\n"));
+        }
+
+        let columns = if self.config.long {
+            try!(self.attribute_columns(entries))
+        } else {
+            Vec::new()
+        };
+
+        for (index, entry) in entries.iter().enumerate() {
+            if several && (index == 0 || entries[index - 1].root() != entry.root()) {
+                let blank = if index == 0 { "" } else { "\n" };
+                try!(writeln!(file, "{}{}\n", blank, section_header(entry.root())));
+            }
 
-        for entry in entries {
             if self.config.verbose {
-                let kind = if entry.path().is_dir() {
+                let kind = if entry.link().is_some() {
+                    "Symlink"
+                } else if entry.is_dir() {
                     "Directory"
                 } else {
                     "File"
@@ -138,17 +426,50 @@ impl App {
                 try!(writeln!(file, "# {} {}", kind, entry.path().display()));
             }
 
-            try!(writeln!(file, "{1: >0$} {2}",
+            if let Some(target) = entry.link() {
+                try!(writeln!(file, "# {}@ -> {}", entry.name().trim_right_matches(MAIN_SEPARATOR), target.display()));
+            }
+
+            let attributes = columns.get(index).map_or(String::new(), |columns| format!(" {}", columns));
+
+            try!(writeln!(file, "{1: >0$}{2} {3}",
                           self.config.hash_width,
                           entry.hash_short(self.config.hash_width),
-                          Formatter::escape(entry.basename())));
+                          attributes,
+                          Formatter::escape(entry.name())));
         }
 
         Ok(())
     }
 
+    // Mode, owner, group and mtime of each entry, aligned in columns
+    fn attribute_columns(&self, entries: &[Entry]) -> Result<Vec<String>> {
+        let mut rows = Vec::new();
+
+        for entry in entries {
+            let metadata = try!(symlink_metadata(entry.path()));
+            rows.push((attr::format_mode(metadata.mode()),
+                       attr::user_name(metadata.uid()),
+                       attr::group_name(metadata.gid()),
+                       attr::format_mtime(metadata.mtime())));
+        }
+
+        let owner_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        let group_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+
+        Ok(rows.into_iter().map(|(mode, owner, group, mtime)| {
+            format!("{0} {1:<4$} {2:<5$} {3}", mode, owner, group, mtime, owner_width, group_width)
+        }).collect())
+    }
+
     fn edit_transforms(&self) -> Result<()> {
-        let edit_cmd = format!("{} {}", self.config.editor, self.config.transforms_path.display());
+        let mut edit_cmd = format!("{} {}", self.config.editor, self.config.transforms_path.display());
+
+        // Stdin has been taken by the paths to edit
+        if self.config.stdin {
+            edit_cmd.push_str(" < /dev/tty");
+        }
+
         let status = try!(Command::new("sh")
                           .arg("-c")
                           .arg(edit_cmd)
@@ -167,112 +488,872 @@ impl App {
         try!(file.read_to_string(&mut input));
 
         let mut parser = Parser::new(&input);
-        parser.parse().map_err(|e| e.into())
+        parser.columns(self.config.long);
+        let transforms = try!(parser.parse());
+
+        // Lines of the headers of the directories edited together
+        let dirs = self.config.dirs();
+        let sections: Vec<(usize, &Path)> = input.lines().enumerate()
+            .filter_map(|(index, line)| {
+                dirs.iter().find(|dir| line.trim_right() == section_header(dir)).map(|dir| (index + 1, *dir))
+            })
+            .collect();
+
+        Ok(transforms.into_iter().map(|transform| {
+            match sections.iter().rev().find(|&&(line, _)| line < transform.line()) {
+                Some(&(_, dir)) => transform.in_dir(dir),
+                None => transform
+            }
+        }).collect())
     }
 
-    fn apply_transforms(&self, entries: &EntryMap, transforms: &[Transform]) -> Result<()> {
+    fn plan_transforms(&self, entries: &EntryMap, transforms: &[Transform]) -> Result<Plan> {
         let mut fmt = Formatter::new();
         let width = width(transforms.len());
         fmt.width(width);
 
+        let mut plan = Plan::new();
+        plan.parents(self.config.parents);
+        plan.relative_links(self.config.relative_links);
+        plan.follow(self.config.follow);
+        // Existing targets are asked about when applying, unless the answer is `no' anyway
+        plan.overwrite(self.config.default_answer != Some(false));
+
         for ref transform in transforms {
-            let result = self.apply_transform(entries, transform, &mut fmt);
+            let result = self.plan_transform(entries, transform, &mut fmt, &mut plan);
             if let Err(e) = result {
+                plan.problem(transform.line(), e.to_string());
+            }
+        }
+
+        try!(plan.validate());
+
+        Ok(plan)
+    }
+
+    fn plan_transform(&self, entries: &EntryMap, transform: &Transform,
+                      fmt: &mut Formatter, plan: &mut Plan) -> Result<()> {
+        let line = transform.line();
+
+        let hash = match transform.hash_fragment() {
+            Some(hash) => hash,
+            None => return self.plan_creation(transform, fmt, plan)
+        };
+
+        let entry = try!(entries.get(hash));
+        let old = entry.path().to_path_buf();
+
+        // New names are relative to the directory the entry is listed in, unless they are absolute
+
+        match *transform {
+            Transform::Rename { ref pattern, .. } => {
+                let path = try!(fmt.format(pattern));
+                plan.rename(line, old, entry.root().join(path));
+            },
+            Transform::Remove { .. } => plan.remove(line, old),
+            Transform::Copy { ref pattern, .. } => {
+                let path = try!(fmt.format(pattern));
+                plan.copy(line, old, entry.root().join(path));
+            },
+            Transform::Symlink { ref pattern, .. } => {
+                let path = try!(fmt.format(pattern));
+                plan.symlink(line, old, entry.root().join(path));
+            },
+            Transform::Hardlink { ref pattern, .. } => {
+                let path = try!(fmt.format(pattern));
+                plan.hardlink(line, old, entry.root().join(path));
+            },
+            Transform::Chmod { mode, .. } => {
+                let metadata = try!(symlink_metadata(&old));
+                let current = metadata.mode() & 0o7777;
+
+                if metadata.file_type().is_symlink() && mode != current {
+                    plan.problem(line, format!("cannot change mode of symlink `{}'", old.display()));
+                } else {
+                    plan.chmod(line, old, current, mode);
+                }
+            },
+            Transform::Chown { ref owner, ref group, .. } => {
+                let metadata = try!(symlink_metadata(&old));
+
+                match (attr::user_id(owner), attr::group_id(group)) {
+                    (Some(uid), Some(gid)) => plan.chown(line, old, (metadata.uid(), metadata.gid()), (uid, gid)),
+                    (None, _) => plan.problem(line, format!("unknown user `{}'", owner)),
+                    (_, None) => plan.problem(line, format!("unknown group `{}'", group))
+                }
+            },
+            Transform::Touch { ref mtime, .. } => {
+                let metadata = try!(symlink_metadata(&old));
+
+                // Times are compared as they are shown, so one which is left alone doesn't change
+                // by an hour that occurs twice when the clocks go back
+                if attr::format_mtime(metadata.mtime()) != *mtime {
+                    match attr::parse_mtime(mtime) {
+                        Some(secs) => plan.touch(line, old, metadata.mtime(), secs),
+                        None => plan.problem(line, format!("invalid time `{}', expected YYYY-MM-DDTHH:MM:SS", mtime))
+                    }
+                }
+            },
+            Transform::Create { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    fn plan_creation(&self, transform: &Transform, fmt: &mut Formatter, plan: &mut Plan) -> Result<()> {
+        if let Transform::Create { ref pattern, ref template, line, ref dir } = *transform {
+            // Lines are created in the directory of their section
+            let dir = dir.as_ref().unwrap_or(&self.config.dir);
+            let name = try!(fmt.format(pattern));
+            let path = dir.join(&name);
+
+            match *template {
+                Some(ref template) => {
+                    let template = match util::expand_home(template) {
+                        Some(template) => dir.join(template),
+                        None => {
+                            plan.problem(line, format!("cannot expand `{}', home directory is unknown", template));
+                            return Ok(());
+                        }
+                    };
+
+                    if symlink_metadata(&template).is_ok() {
+                        plan.copy(line, template, path);
+                    } else {
+                        plan.problem(line, format!("template `{}' does not exist", template.display()));
+                    }
+                },
+                None if name.ends_with('/') => plan.create_dir(line, path),
+                None => plan.create_file(line, path)
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_transforms(&self, entries: &EntryMap, transforms: &[Transform]) -> Result<()> {
+        let plan = match self.plan_transforms(entries, transforms) {
+            Ok(plan) => plan,
+            Err(Error::PlanError(e)) => {
+                // The report tells why nothing has been done
+                try!(self.write_outcome(&[], e.problems()));
+                return Err(Error::PlanError(e));
+            },
+            Err(e) => return Err(e)
+        };
+
+        if let Some(ref path) = self.config.script {
+            return self.write_script(path, &plan.operations());
+        }
+
+        self.apply_operations(&plan.operations())
+    }
+
+    fn write_script(&self, path: &Path, operations: &[Operation]) -> Result<()> {
+        if path == Path::new("-") {
+            let stdout = io::stdout();
+            try!(script::write_script(&mut stdout.lock(), &self.config.dirs(), operations));
+        } else {
+            try!(script::write_script(&mut try!(File::create(path)), &self.config.dirs(), operations));
+            say!(self, "plan written to `{}'", path.display());
+        }
+
+        Ok(())
+    }
+
+    // Show the resolved plan and ask what to do with it
+    fn review(&self, operations: &[Operation]) -> Result<Choice> {
+        if operations.is_empty() {
+            return Ok(Choice::Apply);
+        }
+
+        // Paths are shown in full when several directories are edited
+        let dir = if self.config.extra_dirs.is_empty() { &self.config.dir } else { Path::new("") };
+        let changes = review::changes(dir, operations);
+        let highlight = util::is_terminal(if self.report_to_stdout() { 2 } else { 1 });
+
+        say!(self, "Changes in {}:", quote_dirs(&self.config.dirs()));
+
+        for change in &changes {
+            let overwrites = if change.overwrites { " (overwrites)" } else { "" };
+            let line = format!("{:<8} {}{}", change.kind, change.description, overwrites);
+
+            if highlight && change.destructive() {
+                say!(self, "  \x1b[1;31m{}\x1b[0m", line);
+            } else {
+                say!(self, "  {}", line);
+            }
+        }
+
+        let counts: Vec<_> = review::counts(&changes).into_iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        say!(self, "{}", counts.join(", "));
+
+        let mut console = self.console();
+
+        loop {
+            try!(write!(console, "Apply, edit or quit? (a/e/q) "));
+            try!(console.flush());
+
+            let mut input = String::new();
+
+            // Nothing is changed if the input is closed
+            if try!(self.read_answer(&mut input)) == 0 {
+                return Ok(Choice::Quit);
+            }
+
+            match &input.trim().to_lowercase()[..] {
+                "a" | "apply" => return Ok(Choice::Apply),
+                "e" | "edit" => return Ok(Choice::Edit),
+                "q" | "quit" => return Ok(Choice::Quit),
+                _ => try!(writeln!(console, "answer `a', `e' or `q'"))
+            }
+        }
+    }
+
+    // Show why the transforms can't be applied and offer to fix them, other errors are returned
+    fn review_problems(&self, error: Error) -> Result<Choice> {
+        match error {
+            Error::ParseError(_) | Error::PlanError(_) => say!(self, "{}", error),
+            _ => return Err(error)
+        }
+
+        let mut console = self.console();
+
+        loop {
+            try!(write!(console, "Edit again or quit? (e/q) "));
+            try!(console.flush());
+
+            let mut input = String::new();
+
+            if try!(self.read_answer(&mut input)) == 0 {
+                return Ok(Choice::Quit);
+            }
+
+            match &input.trim().to_lowercase()[..] {
+                "e" | "edit" => return Ok(Choice::Edit),
+                "q" | "quit" => return Ok(Choice::Quit),
+                _ => try!(writeln!(console, "answer `e' or `q'"))
+            }
+        }
+    }
+
+    fn apply_operations(&self, operations: &[Operation]) -> Result<()> {
+        let started = history::now();
+        let mut state = State::new();
+
+        let result = if self.config.on_error == OnError::Rollback && !self.config.dry_run {
+            self.apply_transaction(operations, &mut state)
+        } else {
+            self.apply_each(operations, &mut state)
+        };
+
+        try!(self.write_report(operations, &mut state));
+        try!(result);
+
+        let summary = Summary::new(operations.len(), &state);
+
+        if !operations.is_empty() {
+            say!(self, "summary: {}", summary);
+        }
+
+        try!(self.record_session(started, state.applied));
+
+        if summary.failed > 0 {
+            return Err(Error::ApplyFailure(summary));
+        }
+
+        Ok(())
+    }
+
+    fn apply_each(&self, operations: &[Operation], state: &mut State) -> Result<()> {
+        for operation in operations {
+            if let Err(e) = self.apply_recorded(operation, state) {
                 let mut stderr = io::stderr();
                 try!(writeln!(stderr, "error: {}", e));
+                state.failed += 1;
+
+                if self.config.on_error == OnError::Stop {
+                    break;
+                }
+            }
+
+            if state.quit {
+                break;
             }
         }
 
         Ok(())
     }
 
-    fn yes_or_no(&self, prompt: &str, default: bool) -> io::Result<bool> {
-        if let Some(answer) = self.config.default_answer {
-            return Ok(answer);
+    fn record_session(&self, started: i64, operations: Vec<Operation>) -> Result<()> {
+        if self.config.dry_run || operations.is_empty() {
+            return Ok(());
+        }
+
+        let mut session = Session::new(&self.config.dirs(), started, history::now(), operations);
+        let history = History::new(self.config.history_path());
+        try!(history.save(&mut session));
+
+        Ok(())
+    }
+
+    fn apply_transaction(&self, operations: &[Operation], state: &mut State) -> Result<()> {
+        let mut journal = try!(Journal::create(self.config.journal_path(), operations));
+        let mut undo = Vec::new();
+
+        state.transaction = true;
+
+        for operation in operations {
+            let result = self.apply_recorded(operation, state);
+
+            // Targets replaced by the operation are put back once it is reverted
+            let restores: Vec<_> = state.restores.drain(..).collect();
+
+            for restore in restores {
+                try!(journal.done(&restore));
+                undo.push(restore);
+            }
+
+            match result {
+                Ok(Some(inverse)) => {
+                    try!(journal.done(&inverse));
+                    undo.push(inverse);
+                },
+                // What is done so far is kept when the user quits
+                Ok(None) if state.quit => break,
+                Ok(None) => {},
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    try!(writeln!(stderr, "error: {}", e));
+                    state.failed += 1;
+
+                    let mut summary = Summary::new(operations.len(), state);
+                    say!(self, "summary: {}", summary);
+
+                    if try!(self.rollback(undo)) {
+                        try!(journal.close());
+                        summary.rolled_back = true;
+
+                        for record in &mut state.records {
+                            if record.status == Status::Done {
+                                record.status = Status::RolledBack;
+                            }
+                        }
+                    } else {
+                        try!(writeln!(stderr, "error: rollback is incomplete, see journal `{}'",
+                                      journal.path().display()));
+                    }
+
+                    return Err(Error::ApplyFailure(summary));
+                }
+            }
+        }
+
+        for &(ref staged, ref original) in &state.staged {
+            try!(self.discard(staged, original));
+        }
+
+        try!(journal.close());
+
+        Ok(())
+    }
+
+    fn rollback(&self, mut undo: Vec<Operation>) -> Result<bool> {
+        let mut complete = true;
+
+        while let Some(operation) = undo.pop() {
+            let result = match operation {
+                Operation::Rename { ref from, ref to } => {
+                    say!(self, "restoring `{}' -> `{}'...", from.display(), to.display());
+                    move_entry(from, to)
+                },
+                Operation::RemoveDir { ref path } => {
+                    say!(self, "removing directory `{}'...", path.display());
+                    remove_dir(path)
+                },
+                Operation::Remove { ref path } => {
+                    say!(self, "removing `{}'...", path.display());
+                    copy::remove_entry(path)
+                },
+                Operation::Chmod { ref path, to, .. } => {
+                    say!(self, "restoring mode of `{}'...", path.display());
+                    attr::set_mode(path, to)
+                },
+                Operation::Chown { ref path, to, .. } => {
+                    say!(self, "restoring owner of `{}'...", path.display());
+                    attr::set_owner(path, to.0, to.1)
+                },
+                Operation::Touch { ref path, to, .. } => {
+                    say!(self, "restoring modification time of `{}'...", path.display());
+                    attr::set_mtime(path, to)
+                },
+                _ => Ok(())
+            };
+
+            if let Err(e) = result {
+                let mut stderr = io::stderr();
+                try!(writeln!(stderr, "error: {}", e));
+                complete = false;
+            }
         }
 
-        let mut stdout = io::stdout();
-        let stdin = io::stdin();
+        Ok(complete)
+    }
+
+    fn report_to_stdout(&self) -> bool {
+        self.config.report.is_some() && self.config.report_file.is_none()
+    }
 
-        let suggest = if default {
-            "Y/n"
+    fn console(&self) -> Box<Write> {
+        if self.report_to_stdout() {
+            Box::new(io::stderr())
         } else {
-            "y/N"
+            Box::new(io::stdout())
+        }
+    }
+
+    fn say(&self, args: fmt::Arguments) {
+        let _ = writeln!(self.console(), "{}", args);
+    }
+
+    // Operations which have not been attempted are reported as skipped
+    fn write_report(&self, operations: &[Operation], state: &mut State) -> Result<()> {
+        for operation in &operations[state.records.len()..] {
+            state.records.push(Record::new(operation.clone(), Status::Skipped));
+        }
+
+        self.write_outcome(&state.records, &[])
+    }
+
+    fn write_outcome(&self, records: &[Record], problems: &[Problem]) -> Result<()> {
+        let format = match self.config.report {
+            Some(format) => format,
+            None => return Ok(())
         };
 
+        match format {
+            Report::Json => {
+                let mut output: Box<Write> = match self.config.report_file {
+                    Some(ref path) => Box::new(try!(File::create(path))),
+                    None => Box::new(io::stdout())
+                };
+
+                try!(report::write_json(&mut output, &self.config.dirs(), self.config.dry_run, records, problems));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Answers are read from the terminal when stdin has been taken by the paths to edit
+    fn read_answer(&self, input: &mut String) -> io::Result<usize> {
+        if let Some(ref answers) = self.answers {
+            answers.borrow_mut().read_line(input)
+        } else if self.config.stdin {
+            util::read_tty_line(input)
+        } else {
+            io::stdin().read_line(input)
+        }
+    }
+
+    // Ask whether to replace an existing `target', with `source' shown among the details.
+    // Answers to all of the remaining questions are remembered in `state'.
+    fn overwrite(&self, source: Option<&Path>, target: &Path, state: &mut State) -> io::Result<bool> {
+        if let Some(answer) = self.config.default_answer.or(state.answer) {
+            return Ok(answer);
+        }
+
+        let mut console = self.console();
+
         loop {
-            try!(write!(stdout, "{} ({}) ", prompt, suggest));
-            try!(stdout.flush());
+            try!(write!(console, "target `{}' exists, override? (y/N/a/s/q/d) ", target.display()));
+            try!(console.flush());
 
             let mut input = String::new();
 
-            try!(stdin.read_line(&mut input));
+            try!(self.read_answer(&mut input));
+
+            match Answer::parse(&input) {
+                Some(Answer::Yes) => return Ok(true),
+                Some(Answer::No) => return Ok(false),
+                Some(Answer::YesToAll) => {
+                    state.answer = Some(true);
+                    return Ok(true);
+                },
+                Some(Answer::NoToAll) => {
+                    state.answer = Some(false);
+                    return Ok(false);
+                },
+                Some(Answer::Quit) => {
+                    state.quit = true;
+                    return Ok(false);
+                },
+                Some(Answer::Details) => {
+                    if let Some(source) = source {
+                        try!(writeln!(console, "  source `{}': {}", source.display(), describe_entry(source)));
+                    }
+
+                    try!(writeln!(console, "  target `{}': {}", target.display(), describe_entry(target)));
+                },
+                None => {
+                    try!(writeln!(console, "answer y (yes), n (no), a (yes to all), s (no to all), \
+                                            q (quit) or d (show details)"));
+                }
+            }
+        }
+    }
+
+    // Ask before replacing `target', telling why the operation is not done otherwise
+    fn confirm_overwrite(&self, source: Option<&Path>, target: &Path, state: &mut State) -> io::Result<bool> {
+        if !state.layout.exists(target) || try!(self.overwrite(source, target, state)) {
+            return Ok(true);
+        }
+
+        if state.quit {
+            say!(self, "quit, the remaining operations are skipped");
+        } else {
+            say!(self, "skipped");
+        }
+
+        Ok(false)
+    }
+
+    // Same as `apply_operation', also keeping a record of the outcome for the report
+    fn apply_recorded(&self, operation: &Operation, state: &mut State) -> Result<Option<Operation>> {
+        let applied = state.applied.len();
+        let result = self.apply_operation(operation, state);
+
+        let status = match result {
+            Err(ref e) => Status::Failed(e.to_string()),
+            Ok(_) if state.applied.len() > applied => Status::Done,
+            Ok(_) => Status::Skipped
+        };
+
+        state.records.push(Record::new(operation.clone(), status));
+
+        result
+    }
+
+    // Returns the operation which reverts the applied one, if there is any
+    fn apply_operation(&self, operation: &Operation, state: &mut State) -> Result<Option<Operation>> {
+        match *operation {
+            Operation::Rename { ref from, ref to } => {
+                say!(self, "renaming `{}' -> `{}'... ", from.display(), to.display());
+
+                if !try!(self.confirm_overwrite(Some(from), to, state)) {
+                    return Ok(None);
+                }
+
+                try!(self.clear_target(to, state));
+
+                if !self.config.dry_run {
+                    try!(move_entry(from, to));
+                }
+
+                state.layout.vacate(from);
+                state.layout.occupy(to);
+                state.applied.push(operation.clone());
+
+                Ok(Some(Operation::Rename { from: to.clone(), to: from.clone() }))
+            },
+            Operation::Copy { ref from, ref to } => {
+                say!(self, "copying `{}' -> `{}'... ", from.display(), to.display());
+                self.create_entry(operation, Some(from), to, state, || copy_over(from, to))
+            },
+            Operation::Symlink { ref target, ref path } => {
+                say!(self, "linking `{}' -> `{}'... ", path.display(), target.display());
+                // Relative targets are resolved against the directory of the link
+                let source = path.parent().map_or_else(|| target.clone(), |dir| dir.join(target));
+                self.create_entry(operation, Some(&source), path, state, || symlink_over(target, path))
+            },
+            Operation::Hardlink { ref from, ref to } => {
+                say!(self, "hard linking `{}' -> `{}'... ", to.display(), from.display());
+                self.create_entry(operation, Some(from), to, state, || hard_link_over(from, to))
+            },
+            Operation::CreateFile { ref path } => {
+                say!(self, "creating `{}'... ", path.display());
+                self.create_entry(operation, None, path, state, || create_file_over(path))
+            },
+            Operation::Remove { ref path } => {
+                if self.config.trash {
+                    say!(self, "trash `{}'...", path.display());
+                } else {
+                    say!(self, "remove `{}'...", path.display());
+                }
+
+                let mut inverse = None;
+
+                if state.transaction {
+                    inverse = Some(try!(self.stage(path, state)));
+                } else if !self.config.dry_run {
+                    try!(self.discard(path, path));
+                }
+
+                state.layout.vacate(path);
+                state.applied.push(operation.clone());
+
+                Ok(inverse)
+            },
+            Operation::Chmod { ref path, from, to } => {
+                say!(self, "changing mode of `{}' to {}... ", path.display(), attr::format_mode(to));
+
+                if !self.config.dry_run {
+                    try!(attr::set_mode(path, to));
+                }
+
+                state.applied.push(operation.clone());
+
+                Ok(Some(Operation::Chmod { path: path.clone(), from: to, to: from }))
+            },
+            Operation::Chown { ref path, from, to } => {
+                say!(self, "changing owner of `{}' to {}:{}... ",
+                           path.display(), attr::user_name(to.0), attr::group_name(to.1));
+
+                if !self.config.dry_run {
+                    try!(attr::set_owner(path, to.0, to.1));
+                }
 
-            let answer = input.trim().to_lowercase();
+                state.applied.push(operation.clone());
 
-            match &answer[..] {
-                "y" => return Ok(true),
-                "n" => return Ok(false),
-                "" => return Ok(default),
-                _ => {
-                    try!(writeln!(stdout, "answer `y' or 'n'"));
+                Ok(Some(Operation::Chown { path: path.clone(), from: to, to: from }))
+            },
+            Operation::Touch { ref path, from, to } => {
+                say!(self, "changing modification time of `{}' to {}... ", path.display(), attr::format_mtime(to));
+
+                if !self.config.dry_run {
+                    try!(attr::set_mtime(path, to));
+                }
+
+                state.applied.push(operation.clone());
+
+                Ok(Some(Operation::Touch { path: path.clone(), from: to, to: from }))
+            },
+            Operation::CreateDir { ref path } => {
+                say!(self, "create directory `{}'...", path.display());
+
+                if !self.config.dry_run {
+                    try!(create_dir(path));
+                }
+
+                state.layout.occupy(path);
+                state.applied.push(operation.clone());
+
+                Ok(Some(Operation::RemoveDir { path: path.clone() }))
+            },
+            Operation::RemoveDir { ref path } => {
+                say!(self, "remove directory `{}'...", path.display());
+
+                if !self.config.dry_run {
+                    try!(remove_dir(path));
                 }
+
+                state.layout.vacate(path);
+                state.applied.push(operation.clone());
+
+                Ok(Some(Operation::CreateDir { path: path.clone() }))
             }
         }
     }
 
+    // Put a new entry at `path`, its inverse is removing it
+    fn create_entry<F>(&self, operation: &Operation, source: Option<&Path>, path: &Path, state: &mut State,
+                       create: F) -> Result<Option<Operation>>
+        where F: FnOnce() -> io::Result<()> {
+        if !try!(self.confirm_overwrite(source, path, state)) {
+            return Ok(None);
+        }
+
+        try!(self.clear_target(path, state));
+
+        if !self.config.dry_run {
+            try!(create());
+        }
+
+        state.layout.occupy(path);
+        state.applied.push(operation.clone());
+
+        Ok(Some(Operation::Remove { path: path.to_path_buf() }))
+    }
+
+    // Keep the entry at `path' aside until the whole transaction succeeds,
+    // returns the operation which puts it back
+    fn stage(&self, path: &Path, state: &mut State) -> io::Result<Operation> {
+        let staged = temp_path(path, state.layout.occupied());
+
+        if !self.config.dry_run {
+            try!(rename(path, &staged));
+        }
+
+        state.layout.occupy(&staged);
+        state.layout.vacate(path);
+        state.staged.push((staged.clone(), path.to_path_buf()));
+
+        Ok(Operation::Rename { from: staged, to: path.to_path_buf() })
+    }
+
+    // An existing target is staged in a transaction before it is replaced, so it can be restored
+    fn clear_target(&self, path: &Path, state: &mut State) -> io::Result<()> {
+        if state.transaction && state.layout.exists(path) {
+            let restore = try!(self.stage(path, state));
+            state.restores.push(restore);
+        }
+
+        Ok(())
+    }
+
+    // Remove an entry which is currently located at `path`, originally at `original`
+    #[cfg(feature = "trash")]
+    fn discard(&self, path: &Path, original: &Path) -> io::Result<()> {
+        if self.config.trash {
+            return trash::put(path, original).map(|_| ());
+        }
+
+        copy::remove_entry(path)
+    }
+
+    #[cfg(not(feature = "trash"))]
+    fn discard(&self, path: &Path, _original: &Path) -> io::Result<()> {
+        copy::remove_entry(path)
+    }
+}
+
+// Entries found so far while walking the working directory
+#[derive(Debug)]
+struct Listing {
+    entries: Vec<Entry>,
+    // Short hashes of the entries
+    hashes: HashSet<String>,
+    paths: HashSet<PathBuf>,
+    // Device and inode numbers of the directory being listed and its parents
+    ancestors: Vec<(u64, u64)>,
+    // Ignore files of the directory being listed and its parents
+    ignores: Vec<Ignore>,
+    filter: Filter,
+    // The `--where' expression, with ages counted back from `now'
+    condition: Option<Expr>,
+    now: i64
+}
+
+// Answer to an overwrite question
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    YesToAll,
+    NoToAll,
+    Quit,
+    Details
+}
+
+impl Answer {
+    fn parse(input: &str) -> Option<Self> {
+        match &input.trim().to_lowercase()[..] {
+            "y" | "yes" => Some(Answer::Yes),
+            "n" | "no" | "" => Some(Answer::No),
+            "a" | "all" => Some(Answer::YesToAll),
+            "s" | "none" => Some(Answer::NoToAll),
+            "q" | "quit" => Some(Answer::Quit),
+            "d" | "details" => Some(Answer::Details),
+            _ => None
+        }
+    }
+}
 
-    fn apply_transform(&self, entries: &EntryMap, transform: &Transform, fmt: &mut Formatter) -> Result<()> {
-        let hash = transform.hash_fragment();
-        let entry = try!(entries.get(hash));
-        let old = entry.path();
+fn quote_dirs<P: AsRef<Path>>(dirs: &[P]) -> String {
+    let dirs: Vec<_> = dirs.iter().map(|dir| format!("`{}'", dir.as_ref().display())).collect();
+    dirs.join(", ")
+}
 
-        match *transform {
-            Transform::Rename { ref pattern, .. } => {
-                let path = try!(fmt.format(pattern));
-                let new = self.config.dir.join(path);
+// Comment starting the entries of a directory when several are edited
+fn section_header(dir: &Path) -> String {
+    format!("# {}{}", dir.display(), MAIN_SEPARATOR)
+}
 
-                if old == new {
-                    return Ok(());
-                }
+fn describe_entry(path: &Path) -> String {
+    let metadata = match symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return e.to_string()
+    };
+
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else {
+        "file"
+    };
+
+    format!("{}, {} bytes, modified {}", kind, metadata.len(), attr::format_mtime(metadata.mtime()))
+}
 
-                println!("renaming `{}' -> `{}'... ", old.display(), new.display());
+// Answer to the review of a plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Choice {
+    Apply,
+    Edit,
+    Quit
+}
 
-                if new.exists() {
-                    let prompt = format!("target `{} exists, override?", new.display());
+#[derive(Debug, Default)]
+struct State {
+    layout: Layout,
+    // Removed or replaced entries are staged rather than discarded during a transaction
+    transaction: bool,
+    staged: Vec<(PathBuf, PathBuf)>,
+    // Operations restoring the targets replaced by the last operation
+    restores: Vec<Operation>,
+    applied: Vec<Operation>,
+    records: Vec<Record>,
+    failed: usize,
+    // Remembered answer to overwrite questions
+    answer: Option<bool>,
+    quit: bool
+}
 
-                    if !try!(self.yes_or_no(&prompt, false)) {
-                        println!("skipped");
-                        return Ok(());
-                    }
-                }
+impl State {
+    fn new() -> Self {
+        Self::default()
+    }
+}
 
-                if !self.config.dry_run {
-                    try!(rename(&old, &new));
-                }
-            },
-            Transform::Remove { .. } => {
-                println!("remove `{}'...", old.display());
+// Outcome of applying the planned operations. Skipped ones are either declined
+// at a prompt or not attempted at all after a failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub rolled_back: bool
+}
 
-                if !self.config.dry_run {
-                    try!(if old.is_dir() {
-                        remove_dir_all(&old)
-                    } else {
-                        remove_file(&old)
-                    });
-                }
-            }
+impl Summary {
+    fn new(total: usize, state: &State) -> Self {
+        Summary {
+            succeeded: state.applied.len(),
+            failed: state.failed,
+            skipped: total - state.applied.len() - state.failed,
+            rolled_back: false
         }
+    }
+}
 
-        Ok(())
+impl fmt::Display for Summary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{} succeeded, {} failed, {} skipped", self.succeeded, self.failed, self.skipped)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs::{create_dir, File};
+    use std::fs::{create_dir, metadata, read_link, remove_dir, remove_file, symlink_metadata, File};
+    use std::io::{Cursor, Read, Write};
+    use std::os::unix::fs::{symlink};
     use std::path::{Path, PathBuf};
 
+    use rustc_serialize::json::{Json};
     use tempdir::{TempDir};
 
     use super::*;
@@ -328,6 +1409,35 @@ mod tests {
         }
     }
 
+    fn write_file<P: AsRef<Path>>(path: P, content: &str) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+    }
+
+    fn read_file<P: AsRef<Path>>(path: P) -> String {
+        let mut file = File::open(path).unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    // Names of the listed entries in tree order
+    fn names(app: &mut App) -> Vec<String> {
+        let mut entries = app.list_entries().unwrap();
+        entries.sort();
+        entries.iter().map(|e| String::from(e.name())).collect()
+    }
+
+    fn rename(entries: &[Entry], from: &str, to: &str) -> Transform {
+        let entry = entries.iter().find(|e| e.basename() == from).unwrap();
+        Transform::rename(String::from(entry.hash()), String::from(to))
+    }
+
+    fn remove(entries: &[Entry], name: &str) -> Transform {
+        let entry = entries.iter().find(|e| e.basename() == name).unwrap();
+        Transform::remove(String::from(entry.hash()))
+    }
+
     macro_rules! directory {
         ( $name:expr ) => { Directory::new($name, Vec::new()).unwrap() };
         ( $name:expr, [ $( $entry:expr ),+ ]) => {
@@ -354,6 +1464,7 @@ mod tests {
                 let mut config = $crate::config::Config::from(args);
                 config.dir = $dir.path().to_path_buf();
                 config.set_tmp_dir(tmp.path());
+                config.global_ignore_path = None;
 
                 let mut $app = $crate::app::App::new(config);
 
@@ -431,4 +1542,619 @@ mod tests {
             assert_eq!(transforms.len(), 4);
         });
     }
+
+    #[test]
+    fn recursive() {
+        let dir = directory!("recursive", ["a/", "a/x", "a/y/", "a/y/z", ".h/", ".h/w", "b"]);
+
+        app!(app, ["-R"], dir, {
+            assert_eq!(vec!["a/", "a/y/", "a/y/z", "a/x", "b"], names(&mut app));
+        });
+
+        app!(app, ["--max-depth", "2", "--min-depth", "2"], dir, {
+            assert_eq!(vec!["a/y/", "a/x"], names(&mut app));
+        });
+
+        app!(app, ["-n", "-R"], dir, {
+            let entries = app.list_entries().unwrap();
+            let hash = |name| String::from(entries.iter().find(|e| e.name() == name).unwrap().hash());
+            let transforms = vec![Transform::rename(hash("a/x"), String::from("x")),
+                                  Transform::rename(hash("a/y/z"), String::from("a/z"))];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert!(dir.path().join("x").is_file());
+        assert!(dir.path().join("a").join("z").is_file());
+        assert_eq!(0, dir.path().join("a").join("y").read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn ignore_files() {
+        let dir = directory!("ignore", [".gitignore", "a.o", "b.c", "target/", "sub/", "sub/.dedignore",
+                                        "sub/c.o", "sub/d.c"]);
+
+        write_file(dir.path().join(".gitignore"), "*.o\ntarget/\n");
+        write_file(dir.path().join("sub").join(".dedignore"), "!c.o\n*.c\n");
+
+        app!(app, ["-R"], dir, {
+            assert_eq!(vec!["sub/", "sub/c.o", "b.c"], names(&mut app));
+        });
+
+        app!(app, ["-R", "--no-ignore"], dir, {
+            assert_eq!(6, names(&mut app).len());
+        });
+    }
+
+    #[test]
+    fn exclude_and_regex() {
+        let dir = directory!("exclude", ["foo", "foobar/", "Foo.txt", "bar", "src/", "src/foo.rs", "src/bar.rs"]);
+
+        app!(app, ["-m", "foo*", "-x", "*/"], dir, {
+            assert_eq!(vec!["foo"], names(&mut app));
+        });
+
+        app!(app, ["-i", "-m", "foo*", "--exclude-regex", "bar"], dir, {
+            assert_eq!(vec!["Foo.txt", "foo"], names(&mut app));
+        });
+
+        app!(app, ["-R", "--regex", r"\.rs$", "-x", "src/b*"], dir, {
+            assert_eq!(vec!["src/foo.rs"], names(&mut app));
+        });
+
+        app!(app, ["-R", "-m", "*.rs", "--regex", "^bar$"], dir, {
+            assert_eq!(vec!["src/bar.rs", "src/foo.rs", "bar"], names(&mut app));
+        });
+
+        let mut config = Config::from(::cli::args_from(vec!["ded", "--regex", "("]));
+        config.dir = dir.path().to_path_buf();
+        assert!(App::new(config).list_entries().is_err());
+    }
+
+    #[test]
+    fn where_expr() {
+        let dir = directory!("where", ["big.JPG", "small.png", "d/", "d/big.jpg"]);
+
+        for name in &["big.JPG", "d/big.jpg"] {
+            File::create(dir.path().join(name)).unwrap().write_all(&[0; 2048]).unwrap();
+        }
+
+        app!(app, ["-w", "type = file and size > 1K"], dir, {
+            assert_eq!(vec!["big.JPG"], names(&mut app));
+        });
+
+        app!(app, ["-R", "--where", "ext in (jpg, png) and not size > 1K or type = dir"], dir, {
+            assert_eq!(vec!["d/", "small.png"], names(&mut app));
+        });
+
+        app!(app, ["-R", "-w", "ext = jpg and mtime < 1h", "-x", "d/*"], dir, {
+            assert_eq!(vec!["big.JPG"], names(&mut app));
+        });
+
+        let mut config = Config::from(::cli::args_from(vec!["ded", "-w", "size > 10X"]));
+        config.dir = dir.path().to_path_buf();
+        assert!(App::new(config).list_entries().is_err());
+    }
+
+    #[test]
+    fn stdin_paths() {
+        let dir = directory!("stdin", ["a.log", "sub/", "sub/b.log", "sub/c.log"]);
+        let other = directory!("stdin-other", ["d.log"]);
+        let d = other.path().join("d.log");
+
+        for name in &["a.log", "sub/b.log", "sub/c.log"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        write_file(&d, "d.log");
+
+        // Names which aren't valid UTF-8 are read as well
+        let raw = dir.path().join("sub").join(OsStr::from_bytes(b"caf\xe9"));
+        write_file(&raw, "");
+
+        app!(app, ["-n"], dir, {
+            let mut listing = app.listing().unwrap();
+            let input = format!("./sub/b.log\0{}\0missing.log\0sub/../a.log\0sub/b.log\0sub/\0", d.display());
+            app.read_entries(&mut input.as_bytes(), &mut listing).unwrap();
+
+            let names: Vec<_> = listing.entries.iter().map(|e| String::from(e.name())).collect();
+            assert_eq!(vec![String::from("sub/b.log"), d.to_string_lossy().into_owned(),
+                            String::from("sub/../a.log"), String::from("sub/")], names);
+
+            let mut listing = app.listing().unwrap();
+            app.read_entries(&mut &b"sub/caf\xe9\n"[..], &mut listing).unwrap();
+            assert_eq!(vec![raw.clone()], listing.entries.iter().map(|e| e.path().to_path_buf()).collect::<Vec<_>>());
+
+            let mut listing = app.listing().unwrap();
+            app.read_entries(&mut "sub/b.log\nsub/c.log\n".as_bytes(), &mut listing).unwrap();
+            assert_eq!(2, listing.entries.len());
+
+            // Targets are relative to the working directory unless they are absolute
+            let entries = listing.entries;
+            let transforms = vec![rename(&entries, "b.log", "sub/e.log"),
+                                  rename(&entries, "c.log", &other.path().join("f.log").to_string_lossy())];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert_eq!("sub/b.log", read_file(dir.path().join("sub/e.log")));
+        assert_eq!("sub/c.log", read_file(other.path().join("f.log")));
+        assert_eq!("d.log", read_file(d));
+    }
+
+    #[test]
+    fn several_dirs() {
+        let a = directory!("several-a", ["x", "y", "sub/"]);
+        let b = directory!("several-b", ["y", "z"]);
+
+        for &(dir, name) in &[(&a, "x"), (&a, "y"), (&b, "y"), (&b, "z")] {
+            write_file(dir.path().join(name), &format!("{}/{}", dir.path().display(), name));
+        }
+
+        app!(app, ["-n", "-R"], a, {
+            app.config.extra_dirs = vec![b.path().to_path_buf()];
+
+            let mut entries = app.list_entries().unwrap();
+            app.arrange(&mut entries);
+            app.write_transforms(&entries).unwrap();
+
+            let names: Vec<_> = entries.iter().map(|e| (e.root(), e.name())).collect();
+            assert_eq!(vec![(a.path(), "sub/"), (a.path(), "x"), (a.path(), "y"), (b.path(), "y"), (b.path(), "z")],
+                       names);
+
+            let contents = read_file(&app.config.transforms_path);
+            let header = |dir: &Directory| format!("# {}/\n", dir.path().display());
+            assert!(contents.find(&header(&a)).unwrap() < contents.find(&header(&b)).unwrap());
+
+            // A line added under the header of a directory creates the entry there
+            write_file(&app.config.transforms_path, &format!("{}* new\n", contents));
+            let created = app.read_transforms().unwrap().into_iter().filter(|t| t.hash_fragment().is_none());
+
+            // Moves between the directories name the other one's path
+            let hash = |dir: &Directory, name| {
+                String::from(entries.iter().find(|e| e.root() == dir.path() && e.name() == name).unwrap().hash())
+            };
+            let mut transforms = vec![Transform::rename(hash(&a, "x"), format!("{}/x", b.path().display())),
+                                      Transform::rename(hash(&b, "z"), String::from("w")),
+                                      Transform::rename(hash(&b, "y"),
+                                                        format!("../{}/y2", a.path().file_name().unwrap().to_string_lossy()))];
+            transforms.extend(created);
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert_eq!(format!("{}/x", a.path().display()), read_file(b.path().join("x")));
+        assert_eq!(format!("{}/z", b.path().display()), read_file(b.path().join("w")));
+        assert_eq!(format!("{}/y", b.path().display()), read_file(a.path().join("y2")));
+        assert!(a.path().join("y").exists());
+        assert!(b.path().join("new").is_file());
+        assert!(!a.path().join("new").exists());
+    }
+
+    #[test]
+    fn symlinks() {
+        let dir = directory!("symlinks", ["d/", "d/x", "file"]);
+
+        symlink("d", dir.path().join("link")).unwrap();
+        symlink("..", dir.path().join("d").join("up")).unwrap();
+        symlink("missing", dir.path().join("broken")).unwrap();
+
+        app!(app, ["-R"], dir, {
+            assert_eq!(vec!["d/", "d/up", "d/x", "broken", "file", "link"], names(&mut app));
+
+            let contents = read_file(&app.config.transforms_path);
+            assert!(contents.contains("# link@ -> d\n"));
+            assert!(contents.contains("# broken@ -> missing\n"));
+        });
+
+        app!(app, ["-R", "--follow"], dir, {
+            // `d/up' leads back to the listed directory, so it is not descended into
+            assert_eq!(vec!["d/", "d/up/", "d/x", "link/", "link/up/", "link/x", "broken", "file"], names(&mut app));
+        });
+
+        app!(app, ["--only", "files"], dir, {
+            assert_eq!(vec!["broken", "file", "link"], names(&mut app));
+        });
+
+        app!(app, ["--follow", "--only", "dirs"], dir, {
+            assert_eq!(vec!["d/", "link/"], names(&mut app));
+        });
+
+        // Removing a link to a directory leaves the directory alone
+        app!(app, ["--follow", "--delete"], dir, {
+            let entries = app.list_entries().unwrap();
+            app.apply_transforms(&EntryMap::from(entries.clone()), &[remove(&entries, "link/")]).unwrap();
+        });
+
+        assert!(symlink_metadata(dir.path().join("link")).is_err());
+        assert!(dir.path().join("d").join("x").exists());
+    }
+
+    #[test]
+    fn permutation() {
+        let dir = directory!("permutation", ["a", "b", "1", "2", "3"]);
+
+        for name in &["a", "b", "1", "2", "3"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        app!(app, ["-n"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "b"),
+                                  rename(&entries, "b", "a"),
+                                  rename(&entries, "1", "2"),
+                                  rename(&entries, "2", "3"),
+                                  rename(&entries, "3", "1")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+        });
+
+        assert_eq!("b", read_file(dir.path().join("a")));
+        assert_eq!("a", read_file(dir.path().join("b")));
+        assert_eq!("3", read_file(dir.path().join("1")));
+        assert_eq!("1", read_file(dir.path().join("2")));
+        assert_eq!("2", read_file(dir.path().join("3")));
+        assert_eq!(5, dir.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn copy() {
+        let dir = directory!("copy", ["a", "b"]);
+
+        for name in &["a", "b"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        app!(app, ["-n", "--delete"], dir, {
+            let entries = app.list_entries().unwrap();
+            let a = entries.iter().find(|e| e.basename() == "a").unwrap();
+            let transforms = vec![Transform::copy(String::from(a.hash()), String::from("a.bak")),
+                                  rename(&entries, "a", "b"),
+                                  remove(&entries, "b")];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert_eq!("a", read_file(dir.path().join("a.bak")));
+        assert_eq!("a", read_file(dir.path().join("b")));
+        assert_eq!(2, dir.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn links() {
+        let dir = directory!("links", ["a", "b", "d/"]);
+
+        for name in &["a", "b"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        app!(app, ["-n", "--relative-links"], dir, {
+            let entries = app.list_entries().unwrap();
+            let hash = |name| String::from(entries.iter().find(|e| e.basename() == name).unwrap().hash());
+            let transforms = vec![Transform::symlink(hash("a"), String::from("d/a")),
+                                  Transform::hardlink(hash("b"), String::from("c")),
+                                  rename(&entries, "a", "e")];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert_eq!(Path::new("../e"), read_link(dir.path().join("d").join("a")).unwrap());
+        assert_eq!("a", read_file(dir.path().join("d").join("a")));
+        assert_eq!(metadata(dir.path().join("b")).unwrap().ino(), metadata(dir.path().join("c")).unwrap().ino());
+    }
+
+    #[test]
+    fn attributes() {
+        let dir = directory!("attributes", ["a", "b"]);
+
+        app!(app, ["-n", "--long"], dir, {
+            let entries = app.list_entries().unwrap();
+            let hash = |name| String::from(entries.iter().find(|e| e.basename() == name).unwrap().hash());
+            let transforms = vec![rename(&entries, "a", "c").at(1),
+                                  Transform::chmod(hash("a"), 0o600).at(1),
+                                  Transform::touch(hash("b"), attr::format_mtime(1461146400)).at(2)];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+
+            let transforms = vec![Transform::chown(hash("b"), String::from("no such user"), String::from("0")).at(1),
+                                  Transform::touch(hash("b"), String::from("2016-04-20")).at(2)];
+
+            match app.apply_transforms(&EntryMap::from(entries.clone()), &transforms) {
+                Err(Error::PlanError(ref e)) => assert_eq!(2, e.problems().len()),
+                result => panic!("unexpected result: {:?}", result)
+            }
+        });
+
+        assert_eq!(0o600, metadata(dir.path().join("c")).unwrap().mode() & 0o7777);
+        assert_eq!(1461146400, metadata(dir.path().join("b")).unwrap().mtime());
+    }
+
+    #[test]
+    fn create() {
+        let dir = directory!("create", ["a"]);
+        write_file(dir.path().join("a"), "a");
+
+        app!(app, ["-n"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "new/a").at(1),
+                                  Transform::create(String::from("new/"), None).at(2),
+                                  Transform::create(String::from("new/%n.txt"), None).at(3),
+                                  Transform::create(String::from("copy"), Some(String::from("new/a"))).at(4)];
+
+            match app.apply_transforms(&EntryMap::from(entries.clone()), &transforms) {
+                Err(Error::PlanError(ref e)) => assert_eq!(1, e.problems().len()),
+                result => panic!("unexpected result: {:?}", result)
+            }
+
+            let transforms = vec![rename(&entries, "a", "new/a").at(1),
+                                  Transform::create(String::from("new/"), None).at(2),
+                                  Transform::create(String::from("new/%n.txt"), None).at(3),
+                                  Transform::create(String::from("copy"), Some(String::from("a"))).at(4)];
+
+            app.apply_transforms(&EntryMap::from(entries.clone()), &transforms).unwrap();
+        });
+
+        assert_eq!("a", read_file(dir.path().join("new").join("a")));
+        assert_eq!("", read_file(dir.path().join("new").join("1.txt")));
+        assert_eq!("a", read_file(dir.path().join("copy")));
+        assert_eq!(2, dir.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn review_problems() {
+        let dir = directory!("review_problems", ["a"]);
+        let log = directory!("review_problems-log");
+        let edits = log.path().join("edits");
+
+        // The editor adds a line with an unknown hash each time it's run
+        let editor = format!("sh -c 'echo \"ffffffff b\" >> \"$0\"; echo >> {}'", edits.display());
+
+        app!(app, ["-r", "-e", &editor[..]], dir, {
+            app.answers(Box::new(Cursor::new("x\ne\nq\n")));
+            app.edit().unwrap();
+        });
+
+        assert_eq!("\n\n", read_file(&edits));
+
+        app!(app, [], dir, {
+            assert_eq!(vec!["a"], names(&mut app));
+        });
+    }
+
+    #[test]
+    fn overwrite_answers() {
+        let dir = directory!("overwrite_answers", ["a", "b", "c", "d", "e", "f", "u", "v", "w", "x", "y", "z"]);
+
+        for name in &["a", "b", "c", "d", "e", "f"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        // Yes to all
+        app!(app, [], dir, {
+            app.answers(Box::new(Cursor::new("a\n")));
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "u"),
+                                  rename(&entries, "b", "v")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+        });
+
+        assert_eq!(("a", "b"), (&read_file(dir.path().join("u"))[..], &read_file(dir.path().join("v"))[..]));
+
+        // No to all
+        app!(app, [], dir, {
+            app.answers(Box::new(Cursor::new("s\n")));
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "c", "w"),
+                                  rename(&entries, "d", "x")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+        });
+
+        assert_eq!(("", ""), (&read_file(dir.path().join("w"))[..], &read_file(dir.path().join("x"))[..]));
+
+        // Quit, nothing else is asked
+        app!(app, [], dir, {
+            app.answers(Box::new(Cursor::new("q\n")));
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "e", "y"),
+                                  rename(&entries, "f", "z")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+        });
+
+        assert_eq!(("", ""), (&read_file(dir.path().join("y"))[..], &read_file(dir.path().join("z"))[..]));
+
+        app!(app, [], dir, {
+            assert_eq!(vec!["c", "d", "e", "f", "u", "v", "w", "x", "y", "z"], names(&mut app));
+        });
+    }
+
+    #[test]
+    fn on_error() {
+        let dir = directory!("on_error", ["a", "b", "c", "g/"]);
+
+        // Overwriting directory `g' with a file fails
+        app!(app, ["-y", "--on-error", "stop"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "g"),
+                                  rename(&entries, "b", "d")];
+
+            match app.apply_transforms(&EntryMap::from(entries), &transforms) {
+                Err(ref e @ Error::ApplyFailure(_)) => assert_eq!(4, e.exit_code()),
+                result => panic!("unexpected result: {:?}", result)
+            }
+        });
+
+        assert!(dir.path().join("b").exists());
+
+        app!(app, ["-y", "--on-error", "continue"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "g"),
+                                  rename(&entries, "b", "d"),
+                                  rename(&entries, "c", "e")];
+
+            match app.apply_transforms(&EntryMap::from(entries), &transforms) {
+                Err(Error::ApplyFailure(ref summary)) => {
+                    assert_eq!((2, 1, 0), (summary.succeeded, summary.failed, summary.skipped));
+                },
+                result => panic!("unexpected result: {:?}", result)
+            }
+        });
+
+        assert!(dir.path().join("d").exists());
+        assert!(dir.path().join("e").exists());
+    }
+
+    #[test]
+    fn transactional() {
+        let dir = directory!("transactional", ["a", "b", "c", "d/", "g/"]);
+
+        for name in &["a", "b", "c"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        // Moving `g' fails once the other changes are made, as it is gone by then
+        app!(app, ["-y", "--transactional", "--delete", "--parents"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![remove(&entries, "d/"),
+                                  rename(&entries, "c", "e/f/c"),
+                                  rename(&entries, "a", "b"),
+                                  rename(&entries, "g/", "h")];
+
+            let operations = app.plan_transforms(&EntryMap::from(entries), &transforms).unwrap().operations();
+            remove_dir(dir.path().join("g")).unwrap();
+
+            assert!(app.apply_operations(&operations).is_err());
+            assert!(!app.config.journal_path().exists());
+        });
+
+        // `b' is restored after it has been replaced by `a'
+        assert_eq!("a", read_file(dir.path().join("a")));
+        assert_eq!("b", read_file(dir.path().join("b")));
+        assert_eq!("c", read_file(dir.path().join("c")));
+        assert!(dir.path().join("d").is_dir());
+        assert_eq!(4, dir.path().read_dir().unwrap().count());
+
+        app!(app, ["-n", "--transactional", "--delete"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "b"),
+                                  rename(&entries, "b", "a"),
+                                  remove(&entries, "d/")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+            assert!(!app.config.journal_path().exists());
+        });
+
+        assert_eq!("b", read_file(dir.path().join("a")));
+        assert_eq!("a", read_file(dir.path().join("b")));
+        assert_eq!(3, dir.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn report() {
+        let dir = directory!("report", ["a", "b", "g/"]);
+        let report_path = dir.path().join("g").join("report.json");
+
+        app!(app, ["-y", "--on-error", "stop", "--report-file", report_path.to_str().unwrap()], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "b", "c"),
+                                  rename(&entries, "a", "g"),
+                                  Transform::create(String::from("d"), None)];
+
+            assert!(app.apply_transforms(&EntryMap::from(entries), &transforms).is_err());
+        });
+
+        let report = Json::from_str(&read_file(&report_path)).unwrap();
+        let operations = report["operations"].as_array().unwrap();
+        let field = |index: usize, name: &str| String::from(operations[index][name].as_string().unwrap_or(""));
+
+        assert_eq!(3, operations.len());
+        assert_eq!(("rename", "done"), (&field(0, "kind")[..], &field(0, "status")[..]));
+        assert_eq!(dir.path().join("c").to_str().unwrap(), field(0, "target"));
+        assert_eq!("failed", field(1, "status"));
+        assert!(!field(1, "error").is_empty());
+        assert_eq!(("create", "skipped"), (&field(2, "kind")[..], &field(2, "status")[..]));
+        assert!(operations[2]["source"].is_null());
+        assert_eq!(Some(dir.path().to_str().unwrap()), report["dirs"][0].as_string());
+
+        // Plans which don't pass validation are reported with their problems
+        app!(app, ["-n", "--report-file", report_path.to_str().unwrap()], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "c", "d").at(1),
+                                  rename(&entries, "a", "g").at(2)];
+
+            assert!(app.apply_transforms(&EntryMap::from(entries), &transforms).is_err());
+        });
+
+        let report = Json::from_str(&read_file(&report_path)).unwrap();
+        assert_eq!(Some("rejected"), report["status"].as_string());
+        assert_eq!(Some(0), report["operations"].as_array().map(|operations| operations.len()));
+        assert_eq!(Some(2), report["problems"][0]["line"].as_u64());
+    }
+
+    #[test]
+    fn answers() {
+        assert_eq!(Some(Answer::Yes), Answer::parse("Y\n"));
+        assert_eq!(Some(Answer::No), Answer::parse("\n"));
+        assert_eq!(Some(Answer::YesToAll), Answer::parse("all"));
+        assert_eq!(Some(Answer::NoToAll), Answer::parse(" s "));
+        assert_eq!(Some(Answer::Quit), Answer::parse("quit"));
+        assert_eq!(Some(Answer::Details), Answer::parse("d"));
+        assert_eq!(None, Answer::parse("maybe"));
+    }
+
+    #[test]
+    fn undo() {
+        let dir = directory!("undo", ["a", "b", "c"]);
+
+        for name in &["a", "b", "c"] {
+            write_file(dir.path().join(name), name);
+        }
+
+        app!(app, ["-n", "--parents"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "b"),
+                                  rename(&entries, "b", "a"),
+                                  rename(&entries, "c", "x/d")];
+
+            app.apply_transforms(&EntryMap::from(entries), &transforms).unwrap();
+            assert_eq!("c", read_file(dir.path().join("x").join("d")));
+
+            // `c' is taken again, so moving `d' back would overwrite it
+            write_file(dir.path().join("c"), "new");
+            assert!(app.undo(None).is_err());
+            remove_file(dir.path().join("c")).unwrap();
+
+            app.undo(None).unwrap();
+            assert!(app.undo(None).is_err());
+        });
+
+        assert_eq!("a", read_file(dir.path().join("a")));
+        assert_eq!("b", read_file(dir.path().join("b")));
+        assert_eq!("c", read_file(dir.path().join("c")));
+        assert_eq!(3, dir.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn validate() {
+        let dir = directory!("validate", ["a", "b", "c/"]);
+
+        app!(app, ["-n"], dir, {
+            let entries = app.list_entries().unwrap();
+            let transforms = vec![rename(&entries, "a", "d").at(1),
+                                  Transform::remove(String::from("xyz")).at(2),
+                                  rename(&entries, "b", "%x").at(3),
+                                  remove(&entries, "a").at(4),
+                                  rename(&entries, "c/", "c/d").at(5)];
+
+            match app.apply_transforms(&EntryMap::from(entries), &transforms) {
+                Err(Error::PlanError(ref e)) => assert_eq!(4, e.problems().len()),
+                result => panic!("unexpected result: {:?}", result)
+            }
+        });
+
+        assert!(dir.path().join("a").exists());
+        assert!(!dir.path().join("d").exists());
+    }
 }
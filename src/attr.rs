@@ -0,0 +1,197 @@
+use std::ffi::{CStr, CString};
+use std::fs::{set_permissions, symlink_metadata, Permissions};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::{OsStrExt};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path};
+
+use libc;
+use time;
+
+// Entry attributes as they are shown in the `--long' listing
+
+const TIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S";
+
+pub fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode & 0o7777)
+}
+
+pub fn user_name(uid: u32) -> String {
+    let name = unsafe {
+        let passwd = libc::getpwuid(uid as libc::uid_t);
+        if passwd.is_null() { None } else { Some(CStr::from_ptr((*passwd).pw_name)) }
+    };
+
+    name.map_or_else(|| uid.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+pub fn group_name(gid: u32) -> String {
+    let name = unsafe {
+        let group = libc::getgrgid(gid as libc::gid_t);
+        if group.is_null() { None } else { Some(CStr::from_ptr((*group).gr_name)) }
+    };
+
+    name.map_or_else(|| gid.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+// Numeric ids are accepted as well as names
+pub fn user_id(name: &str) -> Option<u32> {
+    if let Ok(uid) = name.parse() {
+        return Some(uid);
+    }
+
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return None
+    };
+
+    unsafe {
+        let passwd = libc::getpwnam(name.as_ptr());
+        if passwd.is_null() { None } else { Some((*passwd).pw_uid as u32) }
+    }
+}
+
+pub fn group_id(name: &str) -> Option<u32> {
+    if let Ok(gid) = name.parse() {
+        return Some(gid);
+    }
+
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return None
+    };
+
+    unsafe {
+        let group = libc::getgrnam(name.as_ptr());
+        if group.is_null() { None } else { Some((*group).gr_gid as u32) }
+    }
+}
+
+// Local time with a second precision
+pub fn format_mtime(secs: i64) -> String {
+    let tm = time::at(time::Timespec::new(secs, 0));
+    time::strftime(TIME_FORMAT, &tm).unwrap_or_else(|_| secs.to_string())
+}
+
+pub fn parse_mtime(s: &str) -> Option<i64> {
+    let tm = match time::strptime(s, TIME_FORMAT) {
+        Ok(tm) => tm,
+        Err(_) => return None
+    };
+
+    let mut raw: libc::tm = unsafe { mem::zeroed() };
+    raw.tm_sec = tm.tm_sec;
+    raw.tm_min = tm.tm_min;
+    raw.tm_hour = tm.tm_hour;
+    raw.tm_mday = tm.tm_mday;
+    raw.tm_mon = tm.tm_mon;
+    raw.tm_year = tm.tm_year;
+    raw.tm_isdst = -1;
+
+    let secs = unsafe { libc::mktime(&mut raw) } as i64;
+
+    // `mktime' normalizes out of range fields (e.g. February 30), reject those
+    if format_mtime(secs) == s {
+        Some(secs)
+    } else {
+        None
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte")
+    })
+}
+
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    set_permissions(path, Permissions::from_mode(mode))
+}
+
+// Symlinks are changed themselves rather than what they point to
+pub fn set_owner(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    let path = try!(c_path(path));
+
+    if unsafe { libc::lchown(path.as_ptr(), uid as libc::uid_t, gid as libc::gid_t) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Times are only shown to the second, so the fraction is kept unless the second changes
+pub fn set_mtime(path: &Path, secs: i64) -> io::Result<()> {
+    let nsecs = match symlink_metadata(path) {
+        Ok(ref metadata) if metadata.mtime() == secs => metadata.mtime_nsec(),
+        _ => 0
+    };
+
+    let path = try!(c_path(path));
+
+    let times = [
+        libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT },
+        libc::timespec { tv_sec: secs as libc::time_t, tv_nsec: nsecs as libc::c_long },
+    ];
+
+    let result = unsafe {
+        libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{symlink_metadata, File};
+    use std::os::unix::fs::{MetadataExt};
+
+    use tempdir::{TempDir};
+
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(Some(0), user_id("root"));
+        assert_eq!(Some(0), group_id("0"));
+        assert_eq!(None, user_id("no such user"));
+        assert_eq!(None, group_id("no\0such group"));
+        assert_eq!("root", user_name(0));
+        assert_eq!("4294967294", user_name(4294967294));
+    }
+
+    #[test]
+    fn mtime() {
+        let secs = 1461146400;
+        assert_eq!(Some(secs), parse_mtime(&format_mtime(secs)));
+        assert_eq!(None, parse_mtime("2016-02-30T10:00:00"));
+        assert_eq!(None, parse_mtime("2016-04-20 10:00:00"));
+        assert_eq!(None, parse_mtime("yesterday"));
+    }
+
+    #[test]
+    fn set() {
+        let tmp = TempDir::new("ded-attr").unwrap();
+        let path = tmp.path().join("file");
+        File::create(&path).unwrap();
+
+        let before = symlink_metadata(&path).unwrap();
+        set_mtime(&path, before.mtime()).unwrap();
+
+        let metadata = symlink_metadata(&path).unwrap();
+        assert_eq!((before.mtime(), before.mtime_nsec()), (metadata.mtime(), metadata.mtime_nsec()));
+
+        set_mode(&path, 0o640).unwrap();
+        set_mtime(&path, 1461146400).unwrap();
+
+        let metadata = symlink_metadata(&path).unwrap();
+        assert_eq!("0640", format_mode(metadata.mode()));
+        assert_eq!((1461146400, 0), (metadata.mtime(), metadata.mtime_nsec()));
+
+        set_owner(&path, metadata.uid(), metadata.gid()).unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 use std::env;
 use std::ffi;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 pub fn args<'a>() -> ArgMatches<'a> {
     args_from(env::args_os())
@@ -16,7 +16,9 @@ pub fn args_from<'a, I, T>(iter: I) -> ArgMatches<'a>
         .arg(Arg::with_name("dir")
              .index(1)
              .value_name("DIR")
-             .help("Working directory [defaults to $PWD]"))
+             .multiple(true)
+             .help("Working directories, listed one after another [defaults to $PWD], or `-' to \
+                    edit paths read from stdin, separated by newlines or NUL characters"))
         .arg(Arg::with_name("editor")
              .short("e")
              .long("editor")
@@ -27,6 +29,33 @@ pub fn args_from<'a, I, T>(iter: I) -> ArgMatches<'a>
              .short("a")
              .long("all")
              .help("Don't ignore hidden files and directories"))
+        .arg(Arg::with_name("no-ignore")
+             .long("no-ignore")
+             .help("List entries ignored by .gitignore, .dedignore and the global ignore file \
+                    [$XDG_CONFIG_HOME/ded/ignore]"))
+        .arg(Arg::with_name("follow")
+             .short("L")
+             .long("follow")
+             .help("List symbolic links to directories as directories, and descend into them with \
+                    `--recursive'; the links themselves are still what is renamed or removed"))
+        .arg(Arg::with_name("recursive")
+             .short("R")
+             .long("recursive")
+             .help("List entries of subdirectories as well, by their paths relative to DIR"))
+        .arg(Arg::with_name("max-depth")
+             .long("max-depth")
+             .takes_value(true)
+             .value_name("N")
+             .help("Descend at most N levels below DIR [implies `--recursive']"))
+        .arg(Arg::with_name("min-depth")
+             .long("min-depth")
+             .takes_value(true)
+             .value_name("N")
+             .help("Don't list entries less than N levels below DIR [implies `--recursive']"))
+        .arg(Arg::with_name("long")
+             .short("l")
+             .long("long")
+             .help("List mode, owner, group and modification time as editable columns"))
         .arg(Arg::with_name("verbose")
              .short("v")
              .long("verbose")
@@ -35,15 +64,61 @@ pub fn args_from<'a, I, T>(iter: I) -> ArgMatches<'a>
              .short("y")
              .long("yes")
              .conflicts_with("no")
-             .help("Assume answer `yes' to all questions"))
+             .help("Assume answer `yes' to all questions, existing targets are replaced"))
         .arg(Arg::with_name("no")
              .short("n")
              .long("no")
              .conflicts_with("yes")
-             .help("Assume answer `no' to all questions"))
+             .help("Assume answer `no' to all questions, plans replacing existing targets are rejected"))
+        .arg(Arg::with_name("review")
+             .short("r")
+             .long("review")
+             .help("Show the whole plan and ask once whether to apply it, edit it again or quit"))
         .arg(Arg::with_name("dry-run")
              .long("dry-run")
              .help("Don't take any action, just show which files are modified"))
+        .arg(Arg::with_name("script")
+             .long("script")
+             .takes_value(true)
+             .value_name("PATH")
+             .help("Write the plan as a shell script to PATH instead of applying it (`-' for stdout)"))
+        .arg(Arg::with_name("parents")
+             .short("p")
+             .long("parents")
+             .help("Create missing parent directories of targets"))
+        .arg(Arg::with_name("relative-links")
+             .long("relative-links")
+             .help("Make symbolic links point to their targets by a relative path"))
+        .arg(Arg::with_name("trash")
+             .long("trash")
+             .conflicts_with("delete")
+             .help("Move removed entries to the trash [default if built with `trash' feature]"))
+        .arg(Arg::with_name("delete")
+             .long("delete")
+             .conflicts_with("trash")
+             .help("Delete removed entries permanently"))
+        .arg(Arg::with_name("on-error")
+             .long("on-error")
+             .takes_value(true)
+             .value_name("continue | stop | rollback")
+             .possible_values(&["continue", "stop", "rollback"])
+             .conflicts_with("transactional")
+             .help("What to do when an operation fails [default: continue]"))
+        .arg(Arg::with_name("transactional")
+             .long("transactional")
+             .conflicts_with("on-error")
+             .help("Apply all changes or none: same as `--on-error rollback'"))
+        .arg(Arg::with_name("report")
+             .long("report")
+             .takes_value(true)
+             .value_name("json")
+             .possible_values(&["json"])
+             .help("Print a report of each operation and its outcome"))
+        .arg(Arg::with_name("report-file")
+             .long("report-file")
+             .takes_value(true)
+             .value_name("PATH")
+             .help("Write the report to PATH instead of stdout [implies `--report json']"))
         .arg(Arg::with_name("match")
              .short("m")
              .long("match")
@@ -54,6 +129,56 @@ pub fn args_from<'a, I, T>(iter: I) -> ArgMatches<'a>
              .help("A glob to filter directory entries{n}\
                     Note: this options can occur multiple times{n}")
              .next_line_help(true))
+        .arg(Arg::with_name("exclude")
+             .short("x")
+             .long("exclude")
+             .takes_value(true)
+             .value_name("GLOB")
+             .multiple(true)
+             .number_of_values(1)
+             .help("A glob to leave out directory entries, can occur multiple times"))
+        .arg(Arg::with_name("regex")
+             .long("regex")
+             .takes_value(true)
+             .value_name("REGEX")
+             .multiple(true)
+             .number_of_values(1)
+             .help("A regular expression to filter directory entries, can occur multiple times"))
+        .arg(Arg::with_name("exclude-regex")
+             .long("exclude-regex")
+             .takes_value(true)
+             .value_name("REGEX")
+             .multiple(true)
+             .number_of_values(1)
+             .help("A regular expression to leave out directory entries, can occur multiple times"))
+        .arg(Arg::with_name("ignore-case")
+             .short("i")
+             .long("ignore-case")
+             .help("Match globs and regular expressions regardless of case"))
+        .arg(Arg::with_name("where")
+             .short("w")
+             .long("where")
+             .takes_value(true)
+             .value_name("EXPR")
+             .help("List only entries whose metadata satisfies EXPR, see WHERE EXPRESSIONS below"))
+        .arg(Arg::with_name("sort")
+             .long("sort")
+             .takes_value(true)
+             .value_name("KEY")
+             .possible_values(&["name", "natural", "mtime", "size", "ext", "none"])
+             .help("Order of the listing, which `%n' numbers entries in [default: name]{n}\
+                    `natural' orders numbers by value (file2 before file10), `mtime' and `size'{n}\
+                    put the oldest and smallest first, `none' keeps the directory order{n}")
+             .next_line_help(true))
+        .arg(Arg::with_name("reverse")
+             .long("reverse")
+             .help("Reverse the sort order, directories and hidden entries still go first"))
+        .arg(Arg::with_name("no-dirs-first")
+             .long("no-dirs-first")
+             .help("Don't list directories before other entries"))
+        .arg(Arg::with_name("no-hidden-first")
+             .long("no-hidden-first")
+             .help("Don't list hidden entries before other entries"))
         .arg(Arg::with_name("tmp")
              .short("t")
              .long("tmp-path")
@@ -66,5 +191,41 @@ pub fn args_from<'a, I, T>(iter: I) -> ArgMatches<'a>
              .value_name("dirs | files")
              .possible_values(&["dirs", "files"])
              .help("List only entries of the specified kind"))
+        .after_help("FILTERS:\n    \
+                     An entry is listed if it matches any of `--match' and `--regex' (or none are given)\n    \
+                     and none of `--exclude' and `--exclude-regex'. Globs containing a `/' are matched\n    \
+                     against the path relative to DIR, others against the basename. Regular expressions\n    \
+                     are searched for anywhere in the relative path. Directory names end with a `/'.\n\n\
+                     WHERE EXPRESSIONS:\n    \
+                     Comparisons of a field with a value, combined with `and', `or', `not' and parentheses,\n    \
+                     e.g. `size > 10M and mtime < 30d and ext in (jpg, png)'. The operators are `=', `!=',\n    \
+                     `<', `<=', `>', `>=' and `in (VALUE, ...)'. Symlinks are not followed unless\n    \
+                     `--follow' is given.\n    \
+                     size   bytes, with an optional K, M, G or T suffix\n    \
+                     mtime  age with an s, m, h, d or w suffix (`mtime < 30d' is modified within 30 days),\n    \
+                     ctime  or local time as YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS\n    \
+                     type   file, dir or symlink (`=' and `!=' only)\n    \
+                     ext    extension without the dot, regardless of case (`=' and `!=' only)\n    \
+                     owner  user name or id (`=' and `!=' only)\n    \
+                     mode   octal permissions, e.g. 0644 (`=' and `!=' only)\n    \
+                     nlink  number of hard links\n\n\
+                     EXIT STATUS:\n    \
+                     0  all changes have been applied\n    \
+                     1  any other error\n    \
+                     2  the transforms file cannot be parsed\n    \
+                     3  the transforms are invalid, nothing has been changed\n    \
+                     4  some operations have failed\n    \
+                     5  the editor has exited with an error")
+        .subcommand(SubCommand::with_name("undo")
+                    .about("Revert renames made by the latest session in DIR")
+                    .arg(Arg::with_name("session")
+                         .index(1)
+                         .value_name("SESSION")
+                         .help("Session to revert instead of the latest one"))
+                    .arg(Arg::with_name("list")
+                         .short("l")
+                         .long("list")
+                         .conflicts_with("session")
+                         .help("List recorded sessions")))
         .get_matches_from(iter)
 }
@@ -6,20 +6,55 @@ use std::process::exit;
 
 use clap::{ArgMatches};
 
-use util::{get_editor, sha1, temp_dir};
+use util::{config_dir, get_editor, sha1, temp_dir};
 
 #[derive(Debug)]
 pub struct Config {
     pub dir: PathBuf,
+    // Directories listed after `dir' in the same transforms file
+    pub extra_dirs: Vec<PathBuf>,
+    // Whether the entries are paths read from stdin rather than the contents of `dir'
+    pub stdin: bool,
     pub transforms_path: PathBuf,
     pub editor: String,
     pub hash_width: usize,
     pub show_hidden: bool,
+    pub ignore: bool,
+    pub follow: bool,
+    pub global_ignore_path: Option<PathBuf>,
+    pub max_depth: Option<usize>,
+    pub min_depth: usize,
+    pub long: bool,
     pub verbose: bool,
     pub default_answer: Option<bool>,
+    pub review: bool,
     pub dry_run: bool,
+    pub script: Option<PathBuf>,
+    pub on_error: OnError,
+    pub report: Option<Report>,
+    pub report_file: Option<PathBuf>,
+    pub parents: bool,
+    pub relative_links: bool,
+    pub trash: bool,
     pub globs: Option<Vec<String>>,
+    pub excludes: Vec<String>,
+    pub regexes: Vec<String>,
+    pub exclude_regexes: Vec<String>,
+    pub ignore_case: bool,
+    pub condition: Option<String>,
+    pub sort: Sort,
+    pub reverse: bool,
+    pub dirs_first: bool,
+    pub hidden_first: bool,
     pub only: Option<Only>,
+    pub action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Edit,
+    Undo(Option<String>),
+    History
 }
 
 arg_enum! {
@@ -30,34 +65,122 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    // What the listing is sorted by
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum Sort {
+        Name,
+        Natural,
+        Mtime,
+        Size,
+        Ext,
+        None
+    }
+}
+
+arg_enum! {
+    // What to do when an operation fails
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum OnError {
+        Continue,
+        Stop,
+        Rollback
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum Report {
+        Json
+    }
+}
+
 impl Config {
     pub fn set_tmp_dir<P: AsRef<Path>>(&mut self, tmp_dir: P) -> &mut Self {
         self.transforms_path = tmp_dir.as_ref().join(sha1(&self.dir.to_string_lossy()));
         self
     }
+
+    pub fn dirs(&self) -> Vec<&Path> {
+        let mut dirs = vec![self.dir.as_path()];
+        dirs.extend(self.extra_dirs.iter().map(PathBuf::as_path));
+        dirs
+    }
+
+    pub fn journal_path(&self) -> PathBuf {
+        self.transforms_path.with_extension("journal")
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.transforms_path.with_file_name("history")
+    }
 }
 
 const TMP_PREFIX: &'static str = "ded";
 
 impl<'a> convert::From<ArgMatches<'a>> for Config {
     fn from(args: ArgMatches<'a>) -> Self {
-        let working_dir = args.value_of("dir")
-            .map_or_else(env::current_dir, |dir| PathBuf::from(dir).canonicalize());
+        let dir_args = args.values_of_lossy("dir").unwrap_or_else(Vec::new);
 
-        let editor = args.value_of("editor")
-            .map_or_else(get_editor, String::from);
+        // Paths read from stdin are relative to the current directory
+        let stdin = dir_args.iter().any(|dir| dir == "-");
 
-        let working_dir = match working_dir {
-            Ok(dir) => dir,
-            Err(e) => {
-                let mut stderr = io::stderr();
-                writeln!(stderr, "error: cannot resolve working directory: {}", e).unwrap();
-                exit(1);
+        if stdin && dir_args.len() > 1 {
+            let mut stderr = io::stderr();
+            writeln!(stderr, "error: `-' cannot be given along with directories").unwrap();
+            exit(1);
+        }
+
+        let mut dirs = Vec::new();
+
+        for dir in dir_args.iter().filter(|_| !stdin) {
+            match PathBuf::from(dir).canonicalize() {
+                Ok(dir) => if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                },
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    writeln!(stderr, "error: cannot resolve working directory `{}': {}", dir, e).unwrap();
+                    exit(1);
+                }
             }
-        };
+        }
+
+        if dirs.is_empty() {
+            match env::current_dir() {
+                Ok(dir) => dirs.push(dir),
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    writeln!(stderr, "error: cannot resolve working directory: {}", e).unwrap();
+                    exit(1);
+                }
+            }
+        }
+
+        let working_dir = dirs.remove(0);
+
+        let editor = args.value_of("editor")
+            .map_or_else(get_editor, String::from);
 
         let all = args.is_present("all");
+        let ignore = !args.is_present("no-ignore");
+        let follow = args.is_present("follow");
+
+        // Immediate children of the directory are at depth 1
+        let max_depth = if args.is_present("max-depth") {
+            Some(value_t!(args, "max-depth", usize).unwrap_or_else(|e| e.exit()))
+        } else if args.is_present("recursive") || args.is_present("min-depth") {
+            None
+        } else {
+            Some(1)
+        };
+        let min_depth = if args.is_present("min-depth") {
+            value_t!(args, "min-depth", usize).unwrap_or_else(|e| e.exit())
+        } else {
+            1
+        };
         let verbose = args.is_present("verbose");
+        let long = args.is_present("long");
 
         let path = args.value_of("tmp")
             .map_or_else(|| {
@@ -79,9 +202,54 @@ impl<'a> convert::From<ArgMatches<'a>> for Config {
             None
         };
 
+        let review = args.is_present("review");
         let dry_run = args.is_present("dry-run");
+        let script = args.value_of("script").map(PathBuf::from);
+        let on_error = if args.is_present("transactional") {
+            OnError::Rollback
+        } else if args.is_present("on-error") {
+            value_t!(args, "on-error", OnError).unwrap_or_else(|e| e.exit())
+        } else {
+            OnError::Continue
+        };
+        let report_file = args.value_of("report-file").map(PathBuf::from);
+        let report = if args.is_present("report") {
+            Some(value_t!(args, "report", Report).unwrap_or_else(|e| e.exit()))
+        } else if report_file.is_some() {
+            Some(Report::Json)
+        } else {
+            None
+        };
+        let parents = args.is_present("parents");
+        let relative_links = args.is_present("relative-links");
+
+        let trash = if args.is_present("trash") {
+            if !cfg!(feature = "trash") {
+                let mut stderr = io::stderr();
+                writeln!(stderr, "error: ded is built without trash support").unwrap();
+                exit(1);
+            }
+
+            true
+        } else {
+            !args.is_present("delete") && cfg!(feature = "trash")
+        };
 
         let globs = args.values_of_lossy("match");
+        let excludes = args.values_of_lossy("exclude").unwrap_or_else(Vec::new);
+        let regexes = args.values_of_lossy("regex").unwrap_or_else(Vec::new);
+        let exclude_regexes = args.values_of_lossy("exclude-regex").unwrap_or_else(Vec::new);
+        let ignore_case = args.is_present("ignore-case");
+        let condition = args.value_of("where").map(String::from);
+
+        let sort = if args.is_present("sort") {
+            value_t!(args, "sort", Sort).unwrap_or_else(|e| e.exit())
+        } else {
+            Sort::Name
+        };
+        let reverse = args.is_present("reverse");
+        let dirs_first = !args.is_present("no-dirs-first");
+        let hidden_first = !args.is_present("no-hidden-first");
 
         let only = if args.is_present("only") {
             Some(value_t!(args, "only", Only).unwrap_or_else(|e| e.exit()))
@@ -89,20 +257,52 @@ impl<'a> convert::From<ArgMatches<'a>> for Config {
             None
         };
 
+        let action = match args.subcommand() {
+            ("undo", Some(undo)) if undo.is_present("list") => Action::History,
+            ("undo", Some(undo)) => Action::Undo(undo.value_of("session").map(String::from)),
+            _ => Action::Edit
+        };
+
         let mut transforms_file_name = sha1(&working_dir.to_string_lossy());
         transforms_file_name.push_str(".ded");
 
         Config {
             dir: working_dir,
+            extra_dirs: dirs,
+            stdin: stdin,
             editor: editor,
             transforms_path: path.join(transforms_file_name),
             hash_width: 8,
             show_hidden: all,
+            ignore: ignore,
+            follow: follow,
+            global_ignore_path: config_dir().map(|dir| dir.join("ignore")),
+            max_depth: max_depth,
+            min_depth: min_depth,
+            long: long,
             verbose: verbose,
             default_answer: default_answer,
+            review: review,
             dry_run: dry_run,
+            script: script,
+            on_error: on_error,
+            report: report,
+            report_file: report_file,
+            parents: parents,
+            relative_links: relative_links,
+            trash: trash,
             globs: globs,
+            excludes: excludes,
+            regexes: regexes,
+            exclude_regexes: exclude_regexes,
+            ignore_case: ignore_case,
+            condition: condition,
+            sort: sort,
+            reverse: reverse,
+            dirs_first: dirs_first,
+            hidden_first: hidden_first,
             only: only,
+            action: action,
         }
     }
 }
@@ -0,0 +1,271 @@
+use std::collections::{HashSet};
+use std::ffi::{CString};
+use std::fs::{self, create_dir, hard_link, read_link, remove_dir_all, remove_file, rename, set_permissions,
+              symlink_metadata, File, Metadata, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::ffi::{OsStrExt};
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path};
+
+use libc;
+
+use plan::{temp_path};
+
+// Rename `from` to `to`, falling back to copying when they are on different filesystems
+pub fn move_entry(from: &Path, to: &Path) -> io::Result<()> {
+    match rename(from, to) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => move_across(from, to),
+        result => result
+    }
+}
+
+fn move_across(from: &Path, to: &Path) -> io::Result<()> {
+    try!(copy_over(from, to));
+    remove_entry(from)
+}
+
+pub fn copy_over(from: &Path, to: &Path) -> io::Result<()> {
+    replace(to, |tmp| copy_entry(from, tmp).and_then(|_| verify(from, tmp)))
+}
+
+pub fn symlink_over(target: &Path, path: &Path) -> io::Result<()> {
+    replace(path, |tmp| symlink(target, tmp))
+}
+
+pub fn hard_link_over(from: &Path, to: &Path) -> io::Result<()> {
+    replace(to, |tmp| hard_link(from, tmp))
+}
+
+pub fn create_file_over(path: &Path) -> io::Result<()> {
+    replace(path, |tmp| OpenOptions::new().write(true).create_new(true).open(tmp).map(|_| ()))
+}
+
+// The entry is created under a temporary name next to `path` and renamed over it
+// once complete, so an existing target is replaced the same way `rename' would do it.
+fn replace<F>(path: &Path, create: F) -> io::Result<()>
+    where F: FnOnce(&Path) -> io::Result<()> {
+    let tmp = temp_path(path, &HashSet::new());
+
+    let result = create(&tmp).and_then(|_| rename(&tmp, path));
+
+    if let Err(e) = result {
+        let _ = remove_entry(&tmp);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+// A symbolic link is removed itself, what it points to is left alone
+pub fn remove_entry(path: &Path) -> io::Result<()> {
+    let metadata = try!(symlink_metadata(path));
+
+    if metadata.is_dir() {
+        remove_dir_all(path)
+    } else {
+        remove_file(path)
+    }
+}
+
+// Recursively copy `from` to `to`, preserving permissions, timestamps and symlinks
+pub fn copy_entry(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = try!(symlink_metadata(from));
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        try!(symlink(try!(read_link(from)), to));
+    } else if file_type.is_dir() {
+        try!(create_dir(to));
+
+        for entry in try!(from.read_dir()) {
+            let entry = try!(entry);
+            try!(copy_entry(&entry.path(), &to.join(entry.file_name())));
+        }
+    } else if file_type.is_file() {
+        try!(fs::copy(from, to));
+    } else {
+        // Reading a FIFO would block and reading a device would copy what it produces
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("cannot copy `{}': not a regular file, directory or symlink",
+                                          from.display())));
+    }
+
+    copy_metadata(&metadata, to)
+}
+
+fn copy_metadata(metadata: &Metadata, to: &Path) -> io::Result<()> {
+    // Permissions of symlinks are meaningless, and `chmod' would follow them
+    if !metadata.file_type().is_symlink() {
+        try!(set_permissions(to, metadata.permissions()));
+    }
+
+    set_times(to, metadata)
+}
+
+fn set_times(path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let path = try!(CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte")
+    }));
+
+    let times = [
+        libc::timespec { tv_sec: metadata.atime() as libc::time_t, tv_nsec: metadata.atime_nsec() as libc::c_long },
+        libc::timespec { tv_sec: metadata.mtime() as libc::time_t, tv_nsec: metadata.mtime_nsec() as libc::c_long },
+    ];
+
+    let result = unsafe {
+        libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn mismatch(path: &Path, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("copy of `{}' differs: {}", path.display(), what))
+}
+
+// Check that `to` is an exact copy of `from`
+pub fn verify(from: &Path, to: &Path) -> io::Result<()> {
+    let source = try!(symlink_metadata(from));
+    let copy = try!(symlink_metadata(to));
+    let (source_type, copy_type) = (source.file_type(), copy.file_type());
+
+    if source_type.is_symlink() != copy_type.is_symlink() || source_type.is_dir() != copy_type.is_dir() {
+        return Err(mismatch(from, "file type"));
+    }
+
+    if source_type.is_symlink() {
+        if try!(read_link(from)) != try!(read_link(to)) {
+            return Err(mismatch(from, "link target"));
+        }
+    } else if source_type.is_dir() {
+        let mut count = 0;
+
+        for entry in try!(from.read_dir()) {
+            let entry = try!(entry);
+            try!(verify(&entry.path(), &to.join(entry.file_name())));
+            count += 1;
+        }
+
+        if try!(to.read_dir()).count() != count {
+            return Err(mismatch(from, "directory contents"));
+        }
+    } else if source.len() != copy.len() || !try!(same_contents(from, to)) {
+        return Err(mismatch(from, "contents"));
+    }
+
+    if !source_type.is_symlink() && source.mode() != copy.mode() {
+        return Err(mismatch(from, "permissions"));
+    }
+
+    Ok(())
+}
+
+fn same_contents(this: &Path, that: &Path) -> io::Result<bool> {
+    let mut this = try!(File::open(this));
+    let mut that = try!(File::open(that));
+    let mut this_buf = [0; 8192];
+    let mut that_buf = [0; 8192];
+
+    loop {
+        let n = try!(this.read(&mut this_buf));
+
+        if n == 0 {
+            return Ok(try!(that.read(&mut that_buf)) == 0);
+        }
+
+        // `read' may return less than asked for, so fill the same amount from the other file
+        let mut filled = 0;
+
+        while filled < n {
+            let m = try!(that.read(&mut that_buf[filled..n]));
+
+            if m == 0 {
+                return Ok(false);
+            }
+
+            filled += m;
+        }
+
+        if this_buf[..n] != that_buf[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, read_link, set_permissions, symlink_metadata, File, Permissions};
+    use std::io::{Write};
+    use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+    use tempdir::{TempDir};
+
+    use super::*;
+
+    #[test]
+    fn copy_and_move() {
+        let tmp = TempDir::new("ded-copy").unwrap();
+        let source = tmp.path().join("source");
+        let target = tmp.path().join("target");
+
+        create_dir(&source).unwrap();
+        create_dir(source.join("nested")).unwrap();
+        File::create(source.join("nested").join("file")).unwrap().write_all(b"contents").unwrap();
+        set_permissions(source.join("nested").join("file"), Permissions::from_mode(0o640)).unwrap();
+        symlink("nested/file", source.join("link")).unwrap();
+        symlink("missing", source.join("broken")).unwrap();
+
+        let copy = tmp.path().join("copy");
+        copy_entry(&source, &copy).unwrap();
+        verify(&source, &copy).unwrap();
+
+        let original = symlink_metadata(source.join("nested").join("file")).unwrap();
+        let copied = symlink_metadata(copy.join("nested").join("file")).unwrap();
+        assert_eq!(0o640, copied.mode() & 0o777);
+        assert_eq!((original.mtime(), original.mtime_nsec()), (copied.mtime(), copied.mtime_nsec()));
+        assert_eq!(read_link(source.join("broken")).unwrap(), read_link(copy.join("broken")).unwrap());
+
+        File::create(copy.join("nested").join("file")).unwrap().write_all(b"Contents").unwrap();
+        assert!(verify(&source, &copy).is_err());
+
+        move_across(&source, &target).unwrap();
+        assert!(!source.exists());
+        assert!(symlink_metadata(target.join("link")).unwrap().file_type().is_symlink());
+        assert_eq!(2, tmp.path().read_dir().unwrap().count());
+    }
+
+    #[test]
+    fn special_files() {
+        let tmp = TempDir::new("ded-copy").unwrap();
+        let fifo = tmp.path().join("fifo");
+        let path = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+
+        assert_eq!(0, unsafe { libc::mkfifo(path.as_ptr(), 0o644) });
+
+        assert!(copy_entry(&fifo, &tmp.path().join("copy")).is_err());
+        assert!(symlink_metadata(tmp.path().join("copy")).is_err());
+    }
+
+    #[test]
+    fn remove_links() {
+        let tmp = TempDir::new("ded-remove").unwrap();
+        let path = |name: &str| tmp.path().join(name);
+
+        create_dir(path("d")).unwrap();
+        File::create(path("d/file")).unwrap();
+        create_dir(path("e")).unwrap();
+        symlink("d", path("link")).unwrap();
+        symlink("../d", path("e/back")).unwrap();
+
+        remove_entry(&path("link")).unwrap();
+        remove_entry(&path("e")).unwrap();
+
+        assert!(symlink_metadata(path("link")).is_err());
+        assert!(symlink_metadata(path("e")).is_err());
+        assert!(path("d/file").exists());
+    }
+}
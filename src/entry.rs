@@ -2,6 +2,7 @@ use std::cmp::{Ordering};
 use std::convert;
 use std::error;
 use std::fmt;
+use std::fs::{read_link, symlink_metadata};
 use std::ops::{Deref};
 use std::path::{MAIN_SEPARATOR, Path, PathBuf};
 use std::result;
@@ -14,7 +15,13 @@ use util::{sha1};
 pub struct Entry {
     path: PathBuf,
     hash: String,
-    base: String
+    base: String,
+    name: String,
+    // The directory the name is relative to
+    root: PathBuf,
+    dir: bool,
+    // Target of a symbolic link
+    link: Option<PathBuf>
 }
 
 impl Entry {
@@ -23,22 +30,66 @@ impl Entry {
         Entry::with_hash(hash, path)
     }
 
+    // An entry named by its path relative to `dir`
+    pub fn relative(dir: &Path, path: PathBuf) -> Self {
+        let mut entry = Entry::new(path);
+
+        // Paths outside of `dir' are named in full
+        let mut name = match entry.path.strip_prefix(dir) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => entry.path.to_string_lossy().into_owned()
+        };
+
+        if entry.dir && !name.ends_with(MAIN_SEPARATOR) {
+            name.push(MAIN_SEPARATOR);
+        }
+
+        entry.name = name;
+        entry.root = dir.to_path_buf();
+        entry
+    }
+
+    // Symbolic links are not followed, a link to a directory is not a directory
     fn with_hash(hash: String, path: PathBuf) -> Self {
+        let metadata = symlink_metadata(&path).ok();
+        let dir = metadata.as_ref().map_or(false, |metadata| metadata.is_dir());
+        let link = match metadata {
+            Some(ref metadata) if metadata.file_type().is_symlink() => read_link(&path).ok(),
+            _ => None
+        };
+
         let mut basename = path.file_name()
             .unwrap_or_else(|| path.as_os_str())
             .to_string_lossy().into_owned();
 
-        if path.is_dir() {
+        if dir {
             basename.push(MAIN_SEPARATOR);
         }
 
+        let root = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+
         Entry {
             path: path,
             hash: hash,
-            base: basename
+            name: basename.clone(),
+            base: basename,
+            root: root,
+            dir: dir,
+            link: link
         }
     }
 
+    // A symbolic link to a directory is taken as the directory
+    pub fn follow(mut self) -> Self {
+        if self.link.is_some() && !self.dir && self.path.is_dir() {
+            self.dir = true;
+            self.base.push(MAIN_SEPARATOR);
+            self.name.push(MAIN_SEPARATOR);
+        }
+
+        self
+    }
+
     pub fn hash(&self) -> &str {
         &self.hash
     }
@@ -51,6 +102,15 @@ impl Entry {
         &self.base
     }
 
+    // The name shown in the transforms file, the same as the basename unless it is relative
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     pub fn is_hidden(&self) -> bool {
         self.basename().starts_with('.')
     }
@@ -60,11 +120,44 @@ impl Entry {
     }
 
     pub fn is_dir(&self) -> bool {
-        self.path.is_dir()
+        self.dir
     }
 
-    pub fn is_file(&self) -> bool {
-        self.path.is_file()
+    pub fn link(&self) -> Option<&Path> {
+        self.link.as_ref().map(PathBuf::as_path)
+    }
+
+    fn components(&self) -> Vec<&str> {
+        self.name.trim_right_matches(MAIN_SEPARATOR).split(MAIN_SEPARATOR).collect()
+    }
+
+    // Compares names component by component, the first components which differ
+    // are ordered by `level', which is also told whether they are directories.
+    // A directory goes before its contents.
+    pub fn cmp_components<F>(&self, other: &Self, level: F) -> Ordering
+        where F: Fn((&str, bool), (&str, bool)) -> Ordering {
+        let this = self.components();
+        let that = other.components();
+
+        for (index, (a, b)) in this.iter().zip(that.iter()).enumerate() {
+            if a == b {
+                continue;
+            }
+
+            // All but the last component are directories
+            let a_dir = index + 1 < this.len() || self.dir;
+            let b_dir = index + 1 < that.len() || other.dir;
+
+            return match level((a, a_dir), (b, b_dir)) {
+                Ordering::Equal => a.cmp(b),
+                ordering => ordering
+            };
+        }
+
+        match this.len().cmp(&that.len()) {
+            Ordering::Equal => self.path.cmp(&other.path),
+            ordering => ordering
+        }
     }
 }
 
@@ -79,31 +172,17 @@ impl Eq for Entry {
 
 impl PartialOrd for Entry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let this = &self.path;
-        let that = &other.path;
-
-        let same_type = this.is_dir() == that.is_dir() ||
-            this.is_file() == that.is_file();
-
-        let this_hidden = self.is_hidden();
-        let that_hidden = other.is_hidden();
-
-        if this_hidden != that_hidden {
-            return that_hidden.partial_cmp(&this_hidden);
-        }
-
-        if !same_type {
-            return that.is_dir()
-                .partial_cmp(&this.is_dir())
-        }
-
-        this.partial_cmp(that)
+        Some(self.cmp(other))
     }
 }
 
+// Names are compared component by component, hidden entries go first, then
+// directories, so the contents of a directory follow it.
 impl Ord for Entry {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        self.cmp_components(other, |(a, a_dir), (b, b_dir)| {
+            (!a.starts_with('.'), !a_dir, a).cmp(&(!b.starts_with('.'), !b_dir, b))
+        })
     }
 }
 
@@ -187,7 +266,11 @@ impl convert::From<Vec<Entry>> for EntryMap {
 mod tests {
     use super::*;
 
-    use std::path::{PathBuf};
+    use std::fs::{create_dir};
+    use std::os::unix::fs::{symlink};
+    use std::path::{Path, PathBuf};
+
+    use tempdir::{TempDir};
 
     #[test]
     fn get_all() {
@@ -230,6 +313,51 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn order() {
+        let entry = |name: &str, dir: bool| {
+            let mut entry = Entry::with_hash(String::from(name), PathBuf::from(name));
+            entry.name = String::from(name);
+            entry.dir = dir;
+            entry
+        };
+
+        let mut entries = vec![entry("b", false), entry("a/z", false), entry("a/", true), entry(".c", false),
+                               entry("a/y/", true), entry("a/y/x", false), entry("c/", true)];
+        entries.sort();
+
+        let names: Vec<_> = entries.iter().map(Entry::name).collect();
+        assert_eq!(vec![".c", "a/", "a/y/", "a/y/x", "a/z", "c/", "b"], names);
+    }
+
+    #[test]
+    fn symlinks() {
+        let tmp = TempDir::new("ded-entry").unwrap();
+        let dir = tmp.path();
+
+        create_dir(dir.join("d")).unwrap();
+        symlink("d", dir.join("link")).unwrap();
+        symlink("missing", dir.join("broken")).unwrap();
+
+        let link = Entry::relative(dir, dir.join("link"));
+        assert!(!link.is_dir());
+        assert_eq!("link", link.name());
+        assert_eq!(Some(Path::new("d")), link.link());
+
+        let link = link.follow();
+        assert!(link.is_dir());
+        assert_eq!("link/", link.name());
+        assert_eq!(Some(Path::new("d")), link.link());
+
+        let broken = Entry::relative(dir, dir.join("broken")).follow();
+        assert!(!broken.is_dir());
+        assert_eq!("broken", broken.name());
+
+        let d = Entry::relative(dir, dir.join("d"));
+        assert!(d.is_dir());
+        assert_eq!(None, d.link());
+    }
+
     #[test]
     fn get() {
         let mut map = EntryMap::new();
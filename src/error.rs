@@ -6,20 +6,43 @@ use std::process::{ExitStatus};
 
 use eventual;
 use glob;
+use regex;
 
+use app;
 use entry;
+use expr;
 use formatter;
+use history;
 use parser;
+use plan;
 
 #[derive(Debug)]
 pub enum Error {
     IoError(io::Error),
     FormatError(formatter::Error),
     ParseError(parser::Error),
+    PlanError(plan::Error),
     EntryMapError(entry::Error),
+    HistoryError(history::Error),
     AsyncError(eventual::AsyncError<()>),
     PatternError(glob::PatternError),
-    CmdFailure(ExitStatus)
+    RegexError(regex::Error),
+    ExprError(expr::Error),
+    CmdFailure(ExitStatus),
+    ApplyFailure(app::Summary)
+}
+
+impl Error {
+    // See the `EXIT STATUS' section of the help
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::ParseError(_) => 2,
+            Error::PlanError(_) | Error::FormatError(_) | Error::EntryMapError(_) => 3,
+            Error::ApplyFailure(_) => 4,
+            Error::CmdFailure(_) => 5,
+            _ => 1
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -28,11 +51,19 @@ impl fmt::Display for Error {
             Error::IoError(ref e) => e.fmt(fmt),
             Error::FormatError(ref e) => e.fmt(fmt),
             Error::ParseError(ref e) => e.fmt(fmt),
+            Error::PlanError(ref e) => e.fmt(fmt),
             Error::EntryMapError(ref e) => e.fmt(fmt),
+            Error::HistoryError(ref e) => e.fmt(fmt),
             Error::AsyncError(ref e) => write!(fmt, "{:?}", e),
             Error::PatternError(ref e) => e.fmt(fmt),
+            Error::RegexError(ref e) => e.fmt(fmt),
+            Error::ExprError(ref e) => e.fmt(fmt),
             Error::CmdFailure(code) =>
-                write!(fmt, "Command exited with nonzero code: {}", code)
+                write!(fmt, "Command exited with nonzero code: {}", code),
+            Error::ApplyFailure(ref summary) if summary.rolled_back =>
+                write!(fmt, "Some operations have failed, all changes have been rolled back"),
+            Error::ApplyFailure(ref summary) =>
+                write!(fmt, "Some operations have failed ({})", summary)
         }
     }
 }
@@ -43,10 +74,15 @@ impl error::Error for Error {
             Error::IoError(ref e) => e.description(),
             Error::FormatError(ref e) => e.description(),
             Error::ParseError(ref e) => e.description(),
+            Error::PlanError(ref e) => e.description(),
             Error::EntryMapError(ref e) => e.description(),
+            Error::HistoryError(ref e) => e.description(),
             Error::PatternError(ref e) => e.description(),
+            Error::RegexError(ref e) => e.description(),
+            Error::ExprError(ref e) => e.description(),
             Error::AsyncError(_) => "aborted",
-            Error::CmdFailure(_) => "Command exited with nonzero code"
+            Error::CmdFailure(_) => "Command exited with nonzero code",
+            Error::ApplyFailure(_) => "Some operations have failed"
         }
     }
 
@@ -55,9 +91,13 @@ impl error::Error for Error {
             Error::IoError(ref e) => Some(e),
             Error::FormatError(ref e) => Some(e),
             Error::ParseError(ref e) => Some(e),
+            Error::PlanError(ref e) => Some(e),
             Error::EntryMapError(ref e) => Some(e),
+            Error::HistoryError(ref e) => Some(e),
             Error::PatternError(ref e) => Some(e),
-            Error::AsyncError(_) | Error::CmdFailure(_) => None,
+            Error::RegexError(ref e) => Some(e),
+            Error::ExprError(ref e) => Some(e),
+            Error::AsyncError(_) | Error::CmdFailure(_) | Error::ApplyFailure(_) => None,
         }
     }
 }
@@ -80,12 +120,24 @@ impl convert::From<parser::Error> for Error {
     }
 }
 
+impl convert::From<plan::Error> for Error {
+    fn from(e: plan::Error) -> Self {
+        Error::PlanError(e)
+    }
+}
+
 impl convert::From<entry::Error> for Error {
     fn from(e: entry::Error) -> Self {
         Error::EntryMapError(e)
     }
 }
 
+impl convert::From<history::Error> for Error {
+    fn from(e: history::Error) -> Self {
+        Error::HistoryError(e)
+    }
+}
+
 impl convert::From<eventual::AsyncError<()>> for Error {
     fn from(x: eventual::AsyncError<()>) -> Self {
         Error::AsyncError(x)
@@ -97,3 +149,15 @@ impl convert::From<glob::PatternError> for Error {
         Error::PatternError(glob)
     }
 }
+
+impl convert::From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::RegexError(e)
+    }
+}
+
+impl convert::From<expr::Error> for Error {
+    fn from(e: expr::Error) -> Self {
+        Error::ExprError(e)
+    }
+}
@@ -0,0 +1,552 @@
+use std::cmp::{Ordering};
+use std::error;
+use std::fmt;
+use std::fs::{Metadata};
+use std::os::unix::fs::{MetadataExt};
+use std::path::{Path};
+use std::result;
+
+use attr;
+
+// Conditions on entry metadata given with `--where', such as
+// `size > 10M and mtime < 30d and ext in (jpg, png)'
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct Error {
+    desc: String,
+    col: usize
+}
+
+impl Error {
+    fn new(desc: &str, col: usize) -> Self {
+        Error {
+            desc: String::from(desc),
+            col: col
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Invalid expression at column {}: {}", self.col, self.desc)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Invalid expression"
+    }
+}
+
+pub type Result<R> = result::Result<R, Error>;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Field {
+    Size,
+    Mtime,
+    Ctime,
+    Type,
+    Ext,
+    Owner,
+    Mode,
+    Nlink
+}
+
+const FIELDS: &'static [(&'static str, Field)] = &[
+    ("size", Field::Size),
+    ("mtime", Field::Mtime),
+    ("ctime", Field::Ctime),
+    ("type", Field::Type),
+    ("ext", Field::Ext),
+    ("owner", Field::Owner),
+    ("mode", Field::Mode),
+    ("nlink", Field::Nlink)
+];
+
+impl Field {
+    fn name(self) -> &'static str {
+        FIELDS.iter().find(|&&(_, field)| field == self).map_or("", |&(name, _)| name)
+    }
+
+    // Fields which have no meaningful order are only compared for equality
+    fn is_ordered(self) -> bool {
+        match self {
+            Field::Type | Field::Ext | Field::Owner | Field::Mode => false,
+            _ => true
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+const OPS: &'static [(&'static str, Op)] = &[
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("=", Op::Eq),
+    ("<", Op::Lt),
+    (">", Op::Gt)
+];
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Other
+}
+
+impl Kind {
+    fn of(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+
+        if file_type.is_symlink() {
+            Kind::Symlink
+        } else if file_type.is_dir() {
+            Kind::Dir
+        } else if file_type.is_file() {
+            Kind::File
+        } else {
+            Kind::Other
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Value {
+    // Sizes, modes, link counts and user ids
+    Number(u64),
+    // Seconds before now
+    Age(i64),
+    // Seconds since the epoch
+    Time(i64),
+    Kind(Kind),
+    // Lowercase extensions
+    Text(String)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Expr {
+    Compare(Field, Op, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>)
+}
+
+fn compare<T: Ord>(op: Op, actual: T, expected: T) -> bool {
+    op.holds(actual.cmp(&expected))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase())
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self> {
+        Parser::new(input).parse()
+    }
+
+    // Whether the entry at `path' satisfies the expression, ages are counted back from `now'
+    pub fn matches(&self, path: &Path, metadata: &Metadata, now: i64) -> bool {
+        match *self {
+            Expr::Compare(field, op, ref value) => match (field, value) {
+                (Field::Size, &Value::Number(size)) => compare(op, metadata.len(), size),
+                (Field::Mtime, &Value::Age(age)) => compare(op, now - metadata.mtime(), age),
+                (Field::Mtime, &Value::Time(time)) => compare(op, metadata.mtime(), time),
+                (Field::Ctime, &Value::Age(age)) => compare(op, now - metadata.ctime(), age),
+                (Field::Ctime, &Value::Time(time)) => compare(op, metadata.ctime(), time),
+                (Field::Type, &Value::Kind(kind)) => compare(op, Kind::of(metadata), kind),
+                (Field::Ext, &Value::Text(ref ext)) => compare(op, &extension(path), ext),
+                (Field::Owner, &Value::Number(uid)) => compare(op, metadata.uid() as u64, uid),
+                (Field::Mode, &Value::Number(mode)) => compare(op, (metadata.mode() & 0o7777) as u64, mode),
+                (Field::Nlink, &Value::Number(nlink)) => compare(op, metadata.nlink(), nlink),
+                // The parser never pairs a field with a value of another type
+                _ => false
+            },
+            Expr::Not(ref expr) => !expr.matches(path, metadata, now),
+            Expr::And(ref left, ref right) => left.matches(path, metadata, now) && right.matches(path, metadata, now),
+            Expr::Or(ref left, ref right) => left.matches(path, metadata, now) || right.matches(path, metadata, now)
+        }
+    }
+}
+
+// Characters which end a word, besides whitespace
+const DELIMITERS: &'static str = "(),=!<>";
+
+#[derive(Debug)]
+struct Parser {
+    input: Vec<char>,
+    offset: usize
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Parser {
+            input: input.chars().collect(),
+            offset: 0
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.offset).cloned()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        self.peek().map(|c| {
+            self.offset += 1;
+            c
+        })
+    }
+
+    fn col(&self) -> usize {
+        self.offset + 1
+    }
+
+    fn try_parser<F, U>(&mut self, parser: &F) -> Option<U>
+        where F: Fn(&mut Parser) -> Result<U> {
+        let offset = self.offset;
+        if let Ok(result) = parser(self) {
+            Some(result)
+        } else {
+            self.offset = offset;
+            None
+        }
+    }
+
+    fn ignore_space(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.next_char();
+        }
+    }
+
+    fn char(&mut self, c: char) -> Result<char> {
+        self.ignore_space();
+        let col = self.col();
+
+        match self.next_char() {
+            Some(o) if o == c => Ok(c),
+            Some(_) => Err(Error::new(&format!("expected `{}'", c), col)),
+            None => Err(Error::new(&format!("expected `{}', found end of input", c), col))
+        }
+    }
+
+    fn eof(&mut self) -> Result<()> {
+        self.ignore_space();
+
+        if self.peek().is_none() {
+            Ok(())
+        } else {
+            Err(Error::new("expected `and', `or' or end of input", self.col()))
+        }
+    }
+
+    fn word(&mut self) -> Result<String> {
+        self.ignore_space();
+        let col = self.col();
+        let mut buf = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || DELIMITERS.contains(c) {
+                break;
+            }
+
+            buf.push(c);
+            self.next_char();
+        }
+
+        if buf.is_empty() {
+            Err(Error::new("expected a word", col))
+        } else {
+            Ok(buf)
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<()> {
+        self.ignore_space();
+        let col = self.col();
+
+        if try!(self.word()) == keyword {
+            Ok(())
+        } else {
+            Err(Error::new(&format!("expected `{}'", keyword), col))
+        }
+    }
+
+    fn field(&mut self) -> Result<Field> {
+        self.ignore_space();
+        let col = self.col();
+        let name = try!(self.word().map_err(|_| Error::new("expected a field name", col)));
+
+        FIELDS.iter()
+            .find(|&&(field, _)| field == name)
+            .map(|&(_, field)| field)
+            .ok_or_else(|| {
+                let names: Vec<_> = FIELDS.iter().map(|&(name, _)| name).collect();
+                Error::new(&format!("unknown field `{}', expected one of {}", name, names.join(", ")), col)
+            })
+    }
+
+    fn op(&mut self, field: Field) -> Result<Op> {
+        self.ignore_space();
+        let col = self.col();
+        let rest: String = self.input[self.offset..].iter().take(2).cloned().collect();
+
+        let (symbol, op) = try!(OPS.iter()
+            .find(|&&(symbol, _)| rest.starts_with(symbol))
+            .cloned()
+            .ok_or_else(|| Error::new("expected a comparison operator or `in'", col)));
+
+        if !field.is_ordered() && op != Op::Eq && op != Op::Ne {
+            return Err(Error::new(&format!("`{}' cannot be compared with `{}', only `=' and `!=' are allowed",
+                                           field.name(), symbol), col));
+        }
+
+        self.offset += symbol.len();
+        Ok(op)
+    }
+
+    fn value(&mut self, field: Field) -> Result<Value> {
+        self.ignore_space();
+        let col = self.col();
+        let word = try!(self.word().map_err(|_| Error::new("expected a value", col)));
+
+        let value = match field {
+            Field::Size => parse_size(&word).map(Value::Number)
+                .ok_or("expected a size such as `512', `10K' or `2G'"),
+            Field::Mtime | Field::Ctime => parse_age(&word).map(Value::Age)
+                .or_else(|| parse_date(&word).map(Value::Time))
+                .ok_or("expected an age such as `30d' or a date such as `2016-04-01'"),
+            Field::Type => parse_kind(&word).map(Value::Kind)
+                .ok_or("expected `file', `dir' or `symlink'"),
+            Field::Ext => Ok(Value::Text(word.trim_left_matches('.').to_lowercase())),
+            Field::Owner => return attr::user_id(&word)
+                .map(|uid| Value::Number(uid as u64))
+                .ok_or_else(|| Error::new(&format!("unknown user `{}'", word), col)),
+            Field::Mode => parse_mode(&word).map(Value::Number)
+                .ok_or("expected an octal mode such as `0644'"),
+            Field::Nlink => word.parse().ok().map(Value::Number)
+                .ok_or("expected a number")
+        };
+
+        value.map_err(|desc| Error::new(desc, col))
+    }
+
+    // `FIELD in (VALUE, ...)' is the same as `FIELD = VALUE or ...'
+    fn list(&mut self, field: Field) -> Result<Expr> {
+        try!(self.char('('));
+
+        let mut expr = Expr::Compare(field, Op::Eq, try!(self.value(field)));
+
+        while self.try_parser(&|x: &mut Parser| x.char(',')).is_some() {
+            let value = try!(self.value(field));
+            expr = Expr::Or(Box::new(expr), Box::new(Expr::Compare(field, Op::Eq, value)));
+        }
+
+        try!(self.char(')'));
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let field = try!(self.field());
+
+        if self.try_parser(&|x: &mut Parser| x.keyword("in")).is_some() {
+            return self.list(field);
+        }
+
+        let op = try!(self.op(field));
+        let value = try!(self.value(field));
+
+        Ok(Expr::Compare(field, op, value))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        if self.try_parser(&|x: &mut Parser| x.char('(')).is_some() {
+            let expr = try!(self.disjunction());
+            try!(self.char(')'));
+            return Ok(expr);
+        }
+
+        self.comparison()
+    }
+
+    fn negation(&mut self) -> Result<Expr> {
+        if self.try_parser(&|x: &mut Parser| x.keyword("not")).is_some() {
+            return self.negation().map(|expr| Expr::Not(Box::new(expr)));
+        }
+
+        self.primary()
+    }
+
+    fn conjunction(&mut self) -> Result<Expr> {
+        let mut expr = try!(self.negation());
+
+        while self.try_parser(&|x: &mut Parser| x.keyword("and")).is_some() {
+            let right = try!(self.negation());
+            expr = Expr::And(Box::new(expr), Box::new(right));
+        }
+
+        Ok(expr)
+    }
+
+    fn disjunction(&mut self) -> Result<Expr> {
+        let mut expr = try!(self.conjunction());
+
+        while self.try_parser(&|x: &mut Parser| x.keyword("or")).is_some() {
+            let right = try!(self.conjunction());
+            expr = Expr::Or(Box::new(expr), Box::new(right));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse(&mut self) -> Result<Expr> {
+        let expr = try!(self.disjunction());
+        try!(self.eof());
+        Ok(expr)
+    }
+}
+
+// Bytes, with an optional `K', `M', `G' or `T' suffix for powers of 1024
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, unit) = match s.chars().last() {
+        Some('k') | Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('m') | Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('g') | Some('G') => (&s[..s.len() - 1], 1 << 30),
+        Some('t') | Some('T') => (&s[..s.len() - 1], 1 << 40),
+        _ => (s, 1)
+    };
+
+    digits.parse::<u64>().ok().and_then(|n| n.checked_mul(unit))
+}
+
+// Seconds, minutes, hours, days or weeks, e.g. `12h'
+fn parse_age(s: &str) -> Option<i64> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return None
+    };
+
+    s[..s.len() - 1].parse::<i64>().ok().and_then(|n| n.checked_mul(unit))
+}
+
+// Local time, either midnight of a day or the format of the `--long' listing
+fn parse_date(s: &str) -> Option<i64> {
+    attr::parse_mtime(s).or_else(|| attr::parse_mtime(&format!("{}T00:00:00", s)))
+}
+
+fn parse_kind(s: &str) -> Option<Kind> {
+    match s {
+        "file" | "f" => Some(Kind::File),
+        "dir" | "d" => Some(Kind::Dir),
+        "symlink" | "link" | "l" => Some(Kind::Symlink),
+        _ => None
+    }
+}
+
+fn parse_mode(s: &str) -> Option<u64> {
+    if s.len() > 4 || !s.chars().all(|c| c.is_digit(8)) {
+        return None;
+    }
+
+    u64::from_str_radix(s, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, symlink_metadata, File};
+    use std::io::{Write};
+    use std::os::unix::fs::{symlink};
+
+    use tempdir::{TempDir};
+
+    use attr;
+    use super::*;
+
+    fn compare(field: Field, op: Op, value: Value) -> Box<Expr> {
+        Box::new(Expr::Compare(field, op, value))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(*compare(Field::Size, Op::Gt, Value::Number(10 << 20))), Expr::parse("size > 10M"));
+        assert_eq!(Ok(*compare(Field::Mtime, Op::Le, Value::Age(30 * 24 * 60 * 60))), Expr::parse("mtime<=30d"));
+
+        // `and' binds tighter than `or', `in' is a disjunction of equalities
+        assert_eq!(Ok(Expr::Or(compare(Field::Type, Op::Eq, Value::Kind(Kind::Dir)),
+                               Box::new(Expr::And(
+                                   Box::new(Expr::Not(compare(Field::Nlink, Op::Ne, Value::Number(1)))),
+                                   Box::new(Expr::Or(compare(Field::Ext, Op::Eq, Value::Text(String::from("jpg"))),
+                                                     compare(Field::Ext, Op::Eq, Value::Text(String::from("png"))))))))),
+                   Expr::parse("type = dir or not nlink != 1 and ext in (JPG, .png)"));
+
+        assert_eq!(Expr::parse("mode = 0644 and (size < 1K or ctime > 2016-04-01)").map(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |input| Expr::parse(input).unwrap_err().to_string();
+
+        assert_eq!("Invalid expression at column 1: unknown field `color', expected one of \
+                    size, mtime, ctime, type, ext, owner, mode, nlink", error("color = red"));
+        assert_eq!("Invalid expression at column 8: expected a size such as `512', `10K' or `2G'",
+                   error("size > huge"));
+        assert_eq!("Invalid expression at column 5: `ext' cannot be compared with `<', only `=' and `!=' are allowed",
+                   error("ext < jpg"));
+        assert_eq!("Invalid expression at column 13: expected `and', `or' or end of input", error("size > 1K   size > 2K"));
+        assert_eq!("Invalid expression at column 16: expected `file', `dir' or `symlink'", error("type in (file, pipe)"));
+        assert_eq!("Invalid expression at column 23: expected `)', found end of input", error("(size > 1 or nlink > 2"));
+        assert_eq!("Invalid expression at column 1: expected a field name", error(""));
+    }
+
+    #[test]
+    fn matches() {
+        let tmp = TempDir::new("ded-expr").unwrap();
+        let path = |name: &str| tmp.path().join(name);
+
+        File::create(path("photo.JPG")).unwrap().write_all(&[0; 2048]).unwrap();
+        attr::set_mode(&path("photo.JPG"), 0o600).unwrap();
+        attr::set_mtime(&path("photo.JPG"), 1000000).unwrap();
+        create_dir(path("album.d")).unwrap();
+        symlink("photo.JPG", path("link")).unwrap();
+
+        let now = 1000000 + 2 * 24 * 60 * 60;
+        let matches = |input: &str, name: &str| {
+            Expr::parse(input).unwrap().matches(&path(name), &symlink_metadata(path(name)).unwrap(), now)
+        };
+
+        assert!(matches("size > 1K and size <= 2K", "photo.JPG"));
+        assert!(matches("ext = jpg and type = file and mode = 600", "photo.JPG"));
+        assert!(matches("mtime > 1d and mtime < 1w", "photo.JPG"));
+        assert!(!matches("mtime < 1d", "photo.JPG"));
+        assert!(matches("type = dir and ext = d", "album.d"));
+        assert!(matches("type = symlink", "link"));
+        assert!(!matches("type != symlink", "link"));
+        assert!(matches("not ext in (jpg, png) and nlink >= 1", "link"));
+    }
+}
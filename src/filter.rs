@@ -0,0 +1,133 @@
+use glob::{self, MatchOptions, Pattern};
+use regex::{self, Regex};
+
+#[derive(Debug)]
+enum Matcher {
+    // Globs with a `/' are matched against the relative path, others against the basename
+    Glob(Pattern, bool),
+    // Regexes are searched for anywhere in the relative path
+    Regex(Regex)
+}
+
+// Decides which entries are listed. An entry is listed if it matches any of the
+// included patterns (or there are none of them) and none of the excluded ones.
+#[derive(Debug)]
+pub struct Filter {
+    include: Vec<Matcher>,
+    exclude: Vec<Matcher>,
+    case_sensitive: bool
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            case_sensitive: true
+        }
+    }
+
+    // Regexes are compiled with the case sensitivity set at the time they are added
+    pub fn ignore_case(&mut self, ignore_case: bool) {
+        self.case_sensitive = !ignore_case;
+    }
+
+    fn glob(&self, glob: &str) -> Result<Matcher, glob::PatternError> {
+        Pattern::new(glob).map(|pattern| Matcher::Glob(pattern, glob.trim_right_matches('/').contains('/')))
+    }
+
+    fn regex(&self, regex: &str) -> Result<Matcher, regex::Error> {
+        let regex = if self.case_sensitive {
+            Regex::new(regex)
+        } else {
+            Regex::new(&format!("(?i){}", regex))
+        };
+
+        regex.map(Matcher::Regex)
+    }
+
+    pub fn include_glob(&mut self, glob: &str) -> Result<(), glob::PatternError> {
+        let matcher = try!(self.glob(glob));
+        self.include.push(matcher);
+        Ok(())
+    }
+
+    pub fn exclude_glob(&mut self, glob: &str) -> Result<(), glob::PatternError> {
+        let matcher = try!(self.glob(glob));
+        self.exclude.push(matcher);
+        Ok(())
+    }
+
+    pub fn include_regex(&mut self, regex: &str) -> Result<(), regex::Error> {
+        let matcher = try!(self.regex(regex));
+        self.include.push(matcher);
+        Ok(())
+    }
+
+    pub fn exclude_regex(&mut self, regex: &str) -> Result<(), regex::Error> {
+        let matcher = try!(self.regex(regex));
+        self.exclude.push(matcher);
+        Ok(())
+    }
+
+    fn matches(&self, matcher: &Matcher, name: &str, basename: &str) -> bool {
+        let options = MatchOptions {
+            case_sensitive: self.case_sensitive,
+            require_literal_separator: false,
+            require_literal_leading_dot: false
+        };
+
+        match *matcher {
+            Matcher::Glob(ref pattern, true) => pattern.matches_with(name, &options),
+            Matcher::Glob(ref pattern, false) => pattern.matches_with(basename, &options),
+            Matcher::Regex(ref regex) => regex.is_match(name)
+        }
+    }
+
+    // `name' is the path relative to the working directory, `basename' the last component of it
+    pub fn is_listed(&self, name: &str, basename: &str) -> bool {
+        let included = self.include.is_empty() ||
+            self.include.iter().any(|m| self.matches(m, name, basename));
+
+        included && !self.exclude.iter().any(|m| self.matches(m, name, basename))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let mut filter = Filter::new();
+        assert!(filter.is_listed("src/main.rs", "main.rs"));
+
+        filter.include_glob("*.rs").unwrap();
+        filter.include_regex(r"^doc/.*\.md$").unwrap();
+        filter.exclude_glob("src/*_test.rs").unwrap();
+        filter.exclude_regex("^target/").unwrap();
+
+        assert!(filter.is_listed("src/main.rs", "main.rs"));
+        assert!(filter.is_listed("doc/README.md", "README.md"));
+        assert!(!filter.is_listed("README.md", "README.md"));
+        assert!(!filter.is_listed("src/app_test.rs", "app_test.rs"));
+        assert!(filter.is_listed("tests/app_test.rs", "app_test.rs"));
+        assert!(!filter.is_listed("target/build.rs", "build.rs"));
+        assert!(!filter.is_listed("src/MAIN.RS", "MAIN.RS"));
+
+        assert!(filter.include_glob("[").is_err());
+        assert!(filter.exclude_regex("(").is_err());
+    }
+
+    #[test]
+    fn ignore_case() {
+        let mut filter = Filter::new();
+        filter.ignore_case(true);
+        filter.include_glob("*.jpg").unwrap();
+        filter.include_regex("^IMG_").unwrap();
+
+        assert!(filter.is_listed("PHOTO.JPG", "PHOTO.JPG"));
+        assert!(filter.is_listed("img_0001.png", "img_0001.png"));
+        assert!(!filter.is_listed("photo.png", "photo.png"));
+    }
+}
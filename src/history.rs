@@ -0,0 +1,464 @@
+use std::error;
+use std::fmt;
+use std::fs::{create_dir_all, remove_file, symlink_metadata, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{MetadataExt};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use time;
+
+use journal::{escape_path, format_operation, parse_operation, unescape_path};
+use plan::{Layout, Operation};
+use util::{sha1};
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    Ambiguous(String, Vec<String>),
+    Unsafe(Vec<String>)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound(ref id) =>
+                write!(fmt, "Cannot find session {}", id),
+            Error::Ambiguous(ref id, ref matches) =>
+                write!(fmt, "Ambiguous session {}, matches {}", id, matches.join(", ")),
+            Error::Unsafe(ref problems) =>
+                write!(fmt, "Refusing to undo, directory has changed: {}", problems.join("; "))
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::NotFound(_) => "Cannot find session",
+            Error::Ambiguous(..) => "Ambiguous session",
+            Error::Unsafe(_) => "Directory has changed"
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub fn now() -> i64 {
+    time::get_time().sec
+}
+
+pub fn format_time(secs: i64) -> String {
+    let tm = time::at(time::Timespec::new(secs, 0));
+    time::strftime("%Y-%m-%d %H:%M:%S", &tm).unwrap_or_else(|_| secs.to_string())
+}
+
+// Size and modification time of an entry, to tell whether it has changed since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        symlink_metadata(path).ok().map(|metadata| Stamp {
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec()
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    id: String,
+    // Directories edited together, the first one is given on the command line first
+    dirs: Vec<PathBuf>,
+    started: i64,
+    finished: i64,
+    operations: Vec<Operation>,
+    // Entries created by the session as they were once it finished
+    created: Vec<(PathBuf, Stamp)>
+}
+
+impl Session {
+    pub fn new(dirs: &[&Path], started: i64, finished: i64, operations: Vec<Operation>) -> Self {
+        let created = operations.iter().filter_map(|operation| match *operation {
+            Operation::Copy { ref to, .. } |
+            Operation::Symlink { path: ref to, .. } |
+            Operation::Hardlink { ref to, .. } |
+            Operation::CreateFile { path: ref to } => Stamp::of(to).map(|stamp| (to.clone(), stamp)),
+            _ => None
+        }).collect();
+
+        let first = dirs.first().map_or(String::new(), |dir| dir.to_string_lossy().into_owned());
+
+        Session {
+            id: format!("{}-{}", started, &sha1(&first)[0..8]),
+            dirs: dirs.iter().map(|dir| dir.to_path_buf()).collect(),
+            started: started,
+            finished: finished,
+            operations: operations,
+            created: created
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    pub fn started(&self) -> i64 {
+        self.started
+    }
+
+    pub fn finished(&self) -> i64 {
+        self.finished
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    // Whether an entry created by the session has been modified after it, unless
+    // something else is put in its place while undoing
+    fn is_modified(&self, path: &Path, layout: &Layout) -> bool {
+        if layout.occupied().contains(path) {
+            return false;
+        }
+
+        self.created.iter()
+            .find(|&&(ref created, _)| created == path)
+            .map_or(false, |&(_, stamp)| Stamp::of(path) != Some(stamp))
+    }
+
+    // Operations which revert the session, in the order they should be applied.
+    // Fails if any of them would lose data given the current directory state.
+    pub fn undo_operations(&self) -> Result<Vec<Operation>> {
+        let mut layout = Layout::new();
+        let mut problems = Vec::new();
+        let mut operations = Vec::new();
+
+        for operation in self.operations.iter().rev() {
+            match *operation {
+                Operation::Rename { ref from, ref to } => {
+                    if !layout.exists(to) {
+                        problems.push(format!("`{}' is missing", to.display()));
+                    } else if layout.exists(from) {
+                        problems.push(format!("`{}' already exists", from.display()));
+                    }
+
+                    layout.vacate(to);
+                    layout.occupy(from);
+
+                    operations.push(Operation::Rename { from: to.clone(), to: from.clone() });
+                },
+                Operation::Copy { ref to, .. } |
+                Operation::Symlink { path: ref to, .. } |
+                Operation::Hardlink { ref to, .. } |
+                Operation::CreateFile { path: ref to } => {
+                    if !layout.exists(to) {
+                        problems.push(format!("`{}' is missing", to.display()));
+                    } else if self.is_modified(to, &layout) {
+                        problems.push(format!("`{}' has been modified", to.display()));
+                    }
+
+                    layout.vacate(to);
+
+                    operations.push(Operation::Remove { path: to.clone() });
+                },
+                Operation::Chmod { ref path, from, to } => {
+                    if !layout.exists(path) {
+                        problems.push(format!("`{}' is missing", path.display()));
+                    }
+
+                    operations.push(Operation::Chmod { path: path.clone(), from: to, to: from });
+                },
+                Operation::Chown { ref path, from, to } => {
+                    if !layout.exists(path) {
+                        problems.push(format!("`{}' is missing", path.display()));
+                    }
+
+                    operations.push(Operation::Chown { path: path.clone(), from: to, to: from });
+                },
+                Operation::Touch { ref path, from, to } => {
+                    if !layout.exists(path) {
+                        problems.push(format!("`{}' is missing", path.display()));
+                    }
+
+                    operations.push(Operation::Touch { path: path.clone(), from: to, to: from });
+                },
+                Operation::CreateDir { ref path } => {
+                    let children = path.read_dir()
+                        .map(|entries| entries.filter_map(|e| e.ok()).any(|e| layout.exists(&e.path())));
+
+                    match children {
+                        Ok(false) => {},
+                        Ok(true) => problems.push(format!("`{}' is not empty", path.display())),
+                        Err(_) => problems.push(format!("`{}' is missing", path.display()))
+                    }
+
+                    layout.vacate(path);
+
+                    operations.push(Operation::RemoveDir { path: path.clone() });
+                },
+                _ => {}
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(operations)
+        } else {
+            Err(Error::Unsafe(problems))
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for dir in &self.dirs {
+            try!(writeln!(w, "dir\t{}", escape_path(dir)));
+        }
+
+        try!(writeln!(w, "started\t{}", self.started));
+        try!(writeln!(w, "finished\t{}", self.finished));
+
+        for operation in &self.operations {
+            try!(writeln!(w, "{}", format_operation(operation)));
+        }
+
+        for &(ref path, stamp) in &self.created {
+            try!(writeln!(w, "created\t{}\t{}\t{}\t{}", escape_path(path), stamp.size, stamp.mtime, stamp.mtime_nsec));
+        }
+
+        Ok(())
+    }
+
+    fn read<R: BufRead>(id: &str, r: R) -> io::Result<Self> {
+        let mut session = Session::new(&[], 0, 0, Vec::new());
+        session.id = String::from(id);
+
+        for line in r.lines() {
+            let line = try!(line);
+            let mut fields = line.splitn(2, '\t');
+
+            match (fields.next(), fields.next()) {
+                (Some("dir"), Some(dir)) => session.dirs.push(try!(unescape_path(dir))),
+                (Some("started"), Some(secs)) => session.started = try!(parse_time(secs)),
+                (Some("finished"), Some(secs)) => session.finished = try!(parse_time(secs)),
+                (Some("created"), Some(record)) => session.created.push(try!(parse_created(record))),
+                _ => session.operations.push(try!(parse_operation(&line)))
+            }
+        }
+
+        Ok(session)
+    }
+}
+
+fn parse_time(s: &str) -> io::Result<i64> {
+    s.parse().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("malformed timestamp: {}", s))
+    })
+}
+
+fn parse_created(record: &str) -> io::Result<(PathBuf, Stamp)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed record: {}", record));
+    let fields: Vec<_> = record.split('\t').collect();
+
+    if fields.len() != 4 {
+        return Err(invalid());
+    }
+
+    let stamp = match (fields[1].parse(), fields[2].parse(), fields[3].parse()) {
+        (Ok(size), Ok(mtime), Ok(mtime_nsec)) => Stamp { size: size, mtime: mtime, mtime_nsec: mtime_nsec },
+        _ => return Err(invalid())
+    };
+
+    Ok((try!(unescape_path(fields[0])), stamp))
+}
+
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf
+}
+
+impl History {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        History {
+            path: path.as_ref().to_path_buf()
+        }
+    }
+
+    // Sessions which would get the same id, i.e. started within the same second
+    // in the same directory, are told apart by a counter
+    pub fn save(&self, session: &mut Session) -> io::Result<()> {
+        try!(create_dir_all(&self.path));
+
+        let id = session.id.clone();
+        let mut counter = 0;
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(self.path.join(&session.id)) {
+                Ok(mut file) => return session.write(&mut file),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    counter += 1;
+                    session.id = format!("{}-{}", id, counter);
+                },
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    pub fn forget(&self, session: &Session) -> io::Result<()> {
+        remove_file(self.path.join(session.id()))
+    }
+
+    // All recorded sessions, oldest first
+    pub fn sessions(&self) -> io::Result<Vec<Session>> {
+        let mut sessions = Vec::new();
+
+        if !self.path.is_dir() {
+            return Ok(sessions);
+        }
+
+        for entry in try!(self.path.read_dir()) {
+            let entry = try!(entry);
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let file = try!(File::open(entry.path()));
+            sessions.push(try!(Session::read(&id, BufReader::new(file))));
+        }
+
+        sessions.sort_by(|a, b| (a.started, &a.id).cmp(&(b.started, &b.id)));
+
+        Ok(sessions)
+    }
+}
+
+// Pick a session either by its id (or a unique prefix of it), or the latest one in `dir`
+pub fn select(sessions: Vec<Session>, id: Option<&str>, dir: &Path) -> Result<Session> {
+    let id = match id {
+        Some(id) => id,
+        None => return sessions.into_iter()
+            .filter(|s| s.dirs.iter().any(|d| d == dir))
+            .last()
+            .ok_or_else(|| Error::NotFound(format!("in {}", dir.display())))
+    };
+
+    let mut matches: Vec<_> = sessions.into_iter().filter(|s| s.id.starts_with(id)).collect();
+
+    if let Some(index) = matches.iter().position(|s| s.id == id) {
+        return Ok(matches.swap_remove(index));
+    }
+
+    match matches.len() {
+        0 => Err(Error::NotFound(String::from(id))),
+        1 => Ok(matches.remove(0)),
+        _ => Err(Error::Ambiguous(String::from(id), matches.into_iter().map(|s| s.id).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, File};
+    use std::io::{Write};
+    use std::path::{Path, PathBuf};
+
+    use tempdir::{TempDir};
+
+    use plan::{Operation};
+    use super::*;
+
+    fn rename(from: &Path, to: &Path) -> Operation {
+        Operation::Rename { from: from.to_path_buf(), to: to.to_path_buf() }
+    }
+
+    #[test]
+    fn save_and_select() {
+        let tmp = TempDir::new("ded-history").unwrap();
+        let history = History::new(tmp.path().join("history"));
+
+        assert!(history.sessions().unwrap().is_empty());
+
+        let mut first = Session::new(&[Path::new("/foo")], 10, 11, vec![
+            rename(Path::new("/foo/a\tb"), Path::new("/foo/c")),
+            Operation::Remove { path: PathBuf::from("/foo/d") }
+        ]);
+        let mut second = Session::new(&[Path::new("/foo")], 20, 21, Vec::new());
+        let mut third = Session::new(&[Path::new("/foo")], 20, 22, Vec::new());
+        let mut other = Session::new(&[Path::new("/bar"), Path::new("/baz")], 30, 31, Vec::new());
+
+        history.save(&mut second).unwrap();
+        history.save(&mut first).unwrap();
+        history.save(&mut third).unwrap();
+        history.save(&mut other).unwrap();
+
+        // Started in the same second in the same directory
+        assert!(third.id().starts_with(second.id()) && third.id() != second.id());
+        history.forget(&third).unwrap();
+
+        let sessions = history.sessions().unwrap();
+        assert_eq!(vec![first.id(), second.id(), other.id()],
+                   sessions.iter().map(|s| s.id()).collect::<Vec<_>>());
+        assert_eq!(first.operations(), sessions[0].operations());
+        assert_eq!(&[PathBuf::from("/foo")], sessions[0].dirs());
+        assert_eq!(2, sessions[2].dirs().len());
+        assert_eq!(11, sessions[0].finished());
+
+        assert_eq!(second.id(), select(sessions.clone(), None, Path::new("/foo")).unwrap().id());
+        assert_eq!(first.id(), select(sessions.clone(), Some("10-"), Path::new("/bar")).unwrap().id());
+        assert_eq!(other.id(), select(sessions.clone(), None, Path::new("/baz")).unwrap().id());
+        assert!(select(sessions.clone(), None, Path::new("/qux")).is_err());
+        assert!(select(sessions.clone(), Some("99"), Path::new("/foo")).is_err());
+
+        history.forget(&other).unwrap();
+        assert_eq!(2, history.sessions().unwrap().len());
+    }
+
+    #[test]
+    fn undo_operations() {
+        let tmp = TempDir::new("ded-history").unwrap();
+        let (a, b, c) = (tmp.path().join("a"), tmp.path().join("b"), tmp.path().join("c"));
+
+        File::create(&b).unwrap();
+        File::create(&c).unwrap();
+
+        // a -> b, then c -> a: reverting moves a back to c and b back to a
+        let session = Session::new(&[tmp.path()], 0, 0, vec![rename(&a, &b), rename(&c, &a)]);
+        assert!(session.undo_operations().is_err());
+
+        File::create(&a).unwrap();
+        remove_file(&c).unwrap();
+        assert_eq!(vec![rename(&a, &c), rename(&b, &a)], session.undo_operations().unwrap());
+
+        File::create(&c).unwrap();
+        assert!(session.undo_operations().is_err());
+    }
+
+    #[test]
+    fn modified() {
+        let tmp = TempDir::new("ded-history").unwrap();
+        let history = History::new(tmp.path().join("history"));
+        let (a, b) = (tmp.path().join("a"), tmp.path().join("b"));
+
+        File::create(&a).unwrap();
+        File::create(&b).unwrap();
+
+        let mut session = Session::new(&[tmp.path()], 0, 0, vec![Operation::CreateFile { path: a.clone() },
+                                                                   Operation::Copy { from: a.clone(), to: b.clone() }]);
+        history.save(&mut session).unwrap();
+
+        let session = history.sessions().unwrap().remove(0);
+        assert_eq!(2, session.undo_operations().unwrap().len());
+
+        // Removing the changed copy would lose what has been written to it since
+        File::create(&b).unwrap().write_all(b"changed").unwrap();
+
+        match session.undo_operations() {
+            Err(Error::Unsafe(ref problems)) => assert_eq!(1, problems.len()),
+            result => panic!("unexpected result: {:?}", result)
+        }
+    }
+}
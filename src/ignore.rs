@@ -0,0 +1,183 @@
+use std::fs::{File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+// Names of ignore files looked up in each listed directory, later ones take precedence
+pub const FILE_NAMES: &'static [&'static str] = &[".gitignore", ".dedignore"];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    // Matched against the path relative to the ignore file rather than the basename
+    anchored: bool
+}
+
+impl Rule {
+    // A line of an ignore file in the `gitignore' syntax
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_right();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let negated = line.starts_with('!');
+
+        if negated {
+            line = &line[1..];
+        } else if line.starts_with("\\#") || line.starts_with("\\!") {
+            line = &line[1..];
+        }
+
+        let dir_only = line.ends_with('/');
+        let line = line.trim_right_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_left_matches('/');
+
+        if line.is_empty() {
+            return None;
+        }
+
+        // Invalid patterns are skipped, the same as git does
+        Pattern::new(line).ok().map(|pattern| Rule {
+            pattern: pattern,
+            negated: negated,
+            dir_only: dir_only,
+            anchored: anchored
+        })
+    }
+
+    fn matches(&self, relative: &str, basename: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let name = if self.anchored { relative } else { basename };
+        self.pattern.matches_with(name, &MATCH_OPTIONS)
+    }
+}
+
+// Rules of a single ignore file, which apply to entries below `base`
+#[derive(Debug, Clone)]
+pub struct Ignore {
+    base: PathBuf,
+    rules: Vec<Rule>
+}
+
+impl Ignore {
+    pub fn parse<P: AsRef<Path>>(base: P, input: &str) -> Self {
+        Ignore {
+            base: base.as_ref().to_path_buf(),
+            rules: input.lines().filter_map(Rule::parse).collect()
+        }
+    }
+
+    // Nothing is loaded if the file does not exist
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(base: P, path: Q) -> io::Result<Option<Self>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        let mut input = String::new();
+        try!(file.read_to_string(&mut input));
+
+        Ok(Some(Ignore::parse(base, &input)))
+    }
+
+    // Ignore files found in `dir'
+    pub fn load_dir(dir: &Path) -> io::Result<Vec<Self>> {
+        let mut ignores = Vec::new();
+
+        for name in FILE_NAMES {
+            if let Some(ignore) = try!(Ignore::load(dir, dir.join(name))) {
+                ignores.push(ignore);
+            }
+        }
+
+        Ok(ignores)
+    }
+
+    // Whether `path' is ignored (`Some(true)') or explicitly included (`Some(false)')
+    // by the last rule which matches it
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => return None
+        };
+
+        let basename = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+
+        self.rules.iter().rev()
+            .find(|rule| rule.matches(&relative, &basename, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+// Ignore files are given from the lowest precedence to the highest
+pub fn is_ignored(ignores: &[Ignore], path: &Path, is_dir: bool) -> bool {
+    ignores.iter().rev()
+        .filter_map(|ignore| ignore.decide(path, is_dir))
+        .next()
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path};
+
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let ignore = Ignore::parse("/p", "# build outputs\n\
+                                          target/\n\
+                                          *.o\n\
+                                          !keep.o\n\
+                                          /vendor\n\
+                                          doc/*.html\n\
+                                          **/cache\n\
+                                          \\#notes\n\
+                                          \n");
+        let ignores = vec![ignore];
+        let ignored = |path: &str, is_dir| is_ignored(&ignores, Path::new(path), is_dir);
+
+        assert!(ignored("/p/target", true));
+        assert!(ignored("/p/src/target", true));
+        assert!(!ignored("/p/target", false));
+        assert!(ignored("/p/src/main.o", false));
+        assert!(!ignored("/p/src/keep.o", false));
+        assert!(ignored("/p/vendor", true));
+        assert!(!ignored("/p/src/vendor", true));
+        assert!(ignored("/p/doc/index.html", false));
+        assert!(!ignored("/p/doc/api/index.html", false));
+        assert!(ignored("/p/cache", true));
+        assert!(ignored("/p/a/b/cache", false));
+        assert!(ignored("/p/#notes", false));
+        assert!(!ignored("/p/# build outputs", false));
+        assert!(!ignored("/elsewhere/main.o", false));
+    }
+
+    #[test]
+    fn precedence() {
+        let ignores = vec![Ignore::parse("/", "*.log\n"),
+                           Ignore::parse("/p", "!debug.log\n"),
+                           Ignore::parse("/p/sub", "debug.log\n")];
+        let ignored = |path: &str| is_ignored(&ignores, Path::new(path), false);
+
+        assert!(ignored("/p/error.log"));
+        assert!(!ignored("/p/debug.log"));
+        assert!(ignored("/p/sub/debug.log"));
+    }
+}
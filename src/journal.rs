@@ -0,0 +1,252 @@
+use std::ffi::{OsString};
+use std::fs::{remove_file, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use plan::{Operation};
+
+// A journal is a plain text file, one record per line. It starts with the
+// planned operations, then the inverse of each completed operation is appended
+// as soon as it is done, so an interrupted session can be rolled back by hand.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File
+}
+
+impl Journal {
+    pub fn create<P: AsRef<Path>>(path: P, operations: &[Operation]) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = try!(File::create(&path));
+
+        for operation in operations {
+            try!(writeln!(file, "plan\t{}", format_operation(operation)));
+        }
+
+        try!(file.sync_data());
+
+        Ok(Journal {
+            path: path,
+            file: file
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn done(&mut self, inverse: &Operation) -> io::Result<()> {
+        try!(writeln!(self.file, "undo\t{}", format_operation(inverse)));
+        self.file.sync_data()
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        remove_file(&self.path)
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut buf = String::new();
+
+    for c in s.chars() {
+        match c {
+            '\\' => buf.push_str("\\\\"),
+            '\t' => buf.push_str("\\t"),
+            '\n' => buf.push_str("\\n"),
+            c => buf.push(c)
+        }
+    }
+
+    buf
+}
+
+// Bytes which are not valid UTF-8 are written as `\xNN'
+fn escape_bytes(mut bytes: &[u8]) -> String {
+    let mut buf = String::new();
+
+    loop {
+        let valid_up_to = match str::from_utf8(bytes) {
+            Ok(s) => {
+                buf.push_str(&escape(s));
+                return buf;
+            },
+            Err(e) => e.valid_up_to()
+        };
+
+        let (valid, rest) = bytes.split_at(valid_up_to);
+        buf.push_str(&escape(str::from_utf8(valid).unwrap_or("")));
+        buf.push_str(&format!("\\x{:02x}", rest[0]));
+        bytes = &rest[1..];
+    }
+}
+
+fn unescape(s: &str) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut iter = s.chars();
+
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            buf.extend_from_slice(c.to_string().as_bytes());
+            continue;
+        }
+
+        match iter.next() {
+            Some('\\') => buf.push(b'\\'),
+            Some('t') => buf.push(b'\t'),
+            Some('n') => buf.push(b'\n'),
+            Some('x') => {
+                let hex: String = iter.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => buf.push(byte),
+                    _ => return Err(invalid(s))
+                }
+            },
+            _ => return Err(invalid(s))
+        }
+    }
+
+    Ok(buf)
+}
+
+fn invalid(s: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed record: {}", s))
+}
+
+// Paths are kept byte for byte, so names which are not valid UTF-8 survive
+pub fn escape_path(path: &Path) -> String {
+    escape_bytes(path.as_os_str().as_bytes())
+}
+
+pub fn unescape_path(s: &str) -> io::Result<PathBuf> {
+    unescape(s).map(|bytes| PathBuf::from(OsString::from_vec(bytes)))
+}
+
+pub fn format_operation(operation: &Operation) -> String {
+    match *operation {
+        Operation::Rename { ref from, ref to } =>
+            format!("rename\t{}\t{}", escape_path(from), escape_path(to)),
+        Operation::Copy { ref from, ref to } =>
+            format!("copy\t{}\t{}", escape_path(from), escape_path(to)),
+        Operation::Symlink { ref target, ref path } =>
+            format!("symlink\t{}\t{}", escape_path(target), escape_path(path)),
+        Operation::Hardlink { ref from, ref to } =>
+            format!("hardlink\t{}\t{}", escape_path(from), escape_path(to)),
+        Operation::Remove { ref path } =>
+            format!("remove\t{}", escape_path(path)),
+        Operation::CreateFile { ref path } =>
+            format!("create\t{}", escape_path(path)),
+        Operation::Chmod { ref path, from, to } =>
+            format!("chmod\t{}\t{:o}\t{:o}", escape_path(path), from, to),
+        Operation::Chown { ref path, from, to } =>
+            format!("chown\t{}\t{}:{}\t{}:{}", escape_path(path), from.0, from.1, to.0, to.1),
+        Operation::Touch { ref path, from, to } =>
+            format!("touch\t{}\t{}\t{}", escape_path(path), from, to),
+        Operation::CreateDir { ref path } =>
+            format!("mkdir\t{}", escape_path(path)),
+        Operation::RemoveDir { ref path } =>
+            format!("rmdir\t{}", escape_path(path))
+    }
+}
+
+pub fn parse_operation(record: &str) -> io::Result<Operation> {
+    let mut fields = record.split('\t');
+
+    match (fields.next(), fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some("rename"), Some(from), Some(to), None, None) => Ok(Operation::Rename {
+            from: try!(unescape_path(from)),
+            to: try!(unescape_path(to))
+        }),
+        (Some("copy"), Some(from), Some(to), None, None) => Ok(Operation::Copy {
+            from: try!(unescape_path(from)),
+            to: try!(unescape_path(to))
+        }),
+        (Some("symlink"), Some(target), Some(path), None, None) => Ok(Operation::Symlink {
+            target: try!(unescape_path(target)),
+            path: try!(unescape_path(path))
+        }),
+        (Some("hardlink"), Some(from), Some(to), None, None) => Ok(Operation::Hardlink {
+            from: try!(unescape_path(from)),
+            to: try!(unescape_path(to))
+        }),
+        (Some("remove"), Some(path), None, None, None) => Ok(Operation::Remove {
+            path: try!(unescape_path(path))
+        }),
+        (Some("create"), Some(path), None, None, None) => Ok(Operation::CreateFile {
+            path: try!(unescape_path(path))
+        }),
+        (Some("mkdir"), Some(path), None, None, None) => Ok(Operation::CreateDir {
+            path: try!(unescape_path(path))
+        }),
+        (Some("rmdir"), Some(path), None, None, None) => Ok(Operation::RemoveDir {
+            path: try!(unescape_path(path))
+        }),
+        (Some("chmod"), Some(path), Some(from), Some(to), None) => Ok(Operation::Chmod {
+            path: try!(unescape_path(path)),
+            from: try!(u32::from_str_radix(from, 8).map_err(|_| invalid(record))),
+            to: try!(u32::from_str_radix(to, 8).map_err(|_| invalid(record)))
+        }),
+        (Some("chown"), Some(path), Some(from), Some(to), None) => Ok(Operation::Chown {
+            path: try!(unescape_path(path)),
+            from: try!(parse_owner(from).ok_or_else(|| invalid(record))),
+            to: try!(parse_owner(to).ok_or_else(|| invalid(record)))
+        }),
+        (Some("touch"), Some(path), Some(from), Some(to), None) => Ok(Operation::Touch {
+            path: try!(unescape_path(path)),
+            from: try!(from.parse().map_err(|_| invalid(record))),
+            to: try!(to.parse().map_err(|_| invalid(record)))
+        }),
+        _ => Err(invalid(record))
+    }
+}
+
+fn parse_owner(s: &str) -> Option<(u32, u32)> {
+    let mut ids = s.split(':').map(str::parse);
+
+    match (ids.next(), ids.next(), ids.next()) {
+        (Some(Ok(uid)), Some(Ok(gid)), None) => Some((uid, gid)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr};
+    use std::os::unix::ffi::{OsStrExt};
+    use std::path::{PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let operations = vec![
+            Operation::Rename { from: PathBuf::from("/tmp/foo\tbar"), to: PathBuf::from("/tmp/foo\\n") },
+            Operation::Copy { from: PathBuf::from("/tmp/foo"), to: PathBuf::from("/tmp/bar") },
+            Operation::Symlink { target: PathBuf::from("../foo"), path: PathBuf::from("/tmp/bar") },
+            Operation::Hardlink { from: PathBuf::from("/tmp/foo"), to: PathBuf::from("/tmp/bar") },
+            Operation::Remove { path: PathBuf::from("/tmp/line\nbreak") },
+            Operation::CreateFile { path: PathBuf::from("/tmp/foo") },
+            Operation::Chmod { path: PathBuf::from("/tmp/foo"), from: 0o644, to: 0o4755 },
+            Operation::Chown { path: PathBuf::from("/tmp/foo"), from: (0, 0), to: (1000, 100) },
+            Operation::Touch { path: PathBuf::from("/tmp/foo"), from: 1461146400, to: -1 },
+            Operation::CreateDir { path: PathBuf::from("/tmp/dir") },
+            Operation::RemoveDir { path: PathBuf::from("/tmp/dir") },
+            Operation::Remove { path: PathBuf::from(OsStr::from_bytes(b"/tmp/\xff\\x41\xc3")) },
+        ];
+
+        for operation in &operations {
+            let record = format_operation(operation);
+            assert!(!record.contains('\n'));
+            assert_eq!(operation, &parse_operation(&record).unwrap());
+        }
+
+        assert!(parse_operation("rename\t/tmp/foo").is_err());
+        assert!(parse_operation("remove\t/tmp/foo\\").is_err());
+        assert!(parse_operation("remove\t/tmp/foo\\xf").is_err());
+        assert!(parse_operation("move\t/tmp/foo\t/tmp/bar").is_err());
+        assert!(parse_operation("chmod\t/tmp/foo\t644\t999").is_err());
+        assert!(parse_operation("chown\t/tmp/foo\t0\t0:0").is_err());
+    }
+}
@@ -6,18 +6,36 @@ extern crate clap;
 extern crate crypto;
 extern crate eventual;
 extern crate glob;
+extern crate libc;
+extern crate regex;
+extern crate rustc_serialize;
 extern crate sequence_trie;
+extern crate time;
 
 #[cfg(test)]
 extern crate tempdir;
 
 pub mod app;
+pub mod attr;
 pub mod entry;
 pub mod cli;
 pub mod config;
+pub mod copy;
 pub mod error;
+pub mod expr;
+pub mod filter;
 pub mod formatter;
+pub mod history;
+pub mod ignore;
+pub mod journal;
 pub mod parser;
+pub mod plan;
+pub mod report;
+pub mod review;
+pub mod script;
+pub mod sort;
+#[cfg(feature = "trash")]
+pub mod trash;
 pub mod util;
 
 fn main() {
@@ -29,6 +47,6 @@ fn main() {
     if let Err(e) = app.run() {
         let mut stderr = io::stderr();
         writeln!(stderr, "error: cannot edit directory: {}", e).unwrap();
-        exit(1);
+        exit(e.exit_code());
     }
 }
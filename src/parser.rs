@@ -1,6 +1,7 @@
 use std::cmp::{Ordering};
 use std::error;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use std::result;
 
 #[derive(PartialEq, Eq, Debug)]
@@ -61,10 +62,51 @@ pub type Result<R> = result::Result<R, Error>;
 pub enum Transform {
     Rename {
         hash_fragment: String,
-        pattern: String
+        pattern: String,
+        line: usize
     },
     Remove {
-        hash_fragment: String
+        hash_fragment: String,
+        line: usize
+    },
+    Copy {
+        hash_fragment: String,
+        pattern: String,
+        line: usize
+    },
+    Symlink {
+        hash_fragment: String,
+        pattern: String,
+        line: usize
+    },
+    Hardlink {
+        hash_fragment: String,
+        pattern: String,
+        line: usize
+    },
+    Chmod {
+        hash_fragment: String,
+        mode: u32,
+        line: usize
+    },
+    Chown {
+        hash_fragment: String,
+        owner: String,
+        group: String,
+        line: usize
+    },
+    Touch {
+        hash_fragment: String,
+        mtime: String,
+        line: usize
+    },
+    // New entries do not refer to any existing one
+    Create {
+        pattern: String,
+        template: Option<String>,
+        line: usize,
+        // Directory of the section the line is in, when several are edited
+        dir: Option<PathBuf>
     }
 }
 
@@ -72,20 +114,126 @@ impl Transform {
     pub fn rename(hash_fragment: String, pattern: String) -> Self {
         Transform::Rename {
             hash_fragment: hash_fragment,
-            pattern: pattern
+            pattern: pattern,
+            line: 0
         }
     }
 
     pub fn remove(hash_fragment: String) -> Self {
         Transform::Remove {
-            hash_fragment: hash_fragment
+            hash_fragment: hash_fragment,
+            line: 0
         }
     }
 
-    pub fn hash_fragment(&self) -> &str {
-        match *self {
+    pub fn copy(hash_fragment: String, pattern: String) -> Self {
+        Transform::Copy {
+            hash_fragment: hash_fragment,
+            pattern: pattern,
+            line: 0
+        }
+    }
+
+    pub fn symlink(hash_fragment: String, pattern: String) -> Self {
+        Transform::Symlink {
+            hash_fragment: hash_fragment,
+            pattern: pattern,
+            line: 0
+        }
+    }
+
+    pub fn hardlink(hash_fragment: String, pattern: String) -> Self {
+        Transform::Hardlink {
+            hash_fragment: hash_fragment,
+            pattern: pattern,
+            line: 0
+        }
+    }
+
+    pub fn chmod(hash_fragment: String, mode: u32) -> Self {
+        Transform::Chmod {
+            hash_fragment: hash_fragment,
+            mode: mode,
+            line: 0
+        }
+    }
+
+    pub fn chown(hash_fragment: String, owner: String, group: String) -> Self {
+        Transform::Chown {
+            hash_fragment: hash_fragment,
+            owner: owner,
+            group: group,
+            line: 0
+        }
+    }
+
+    pub fn touch(hash_fragment: String, mtime: String) -> Self {
+        Transform::Touch {
+            hash_fragment: hash_fragment,
+            mtime: mtime,
+            line: 0
+        }
+    }
+
+    pub fn create(pattern: String, template: Option<String>) -> Self {
+        Transform::Create {
+            pattern: pattern,
+            template: template,
+            line: 0,
+            dir: None
+        }
+    }
+
+    pub fn at(mut self, line_number: usize) -> Self {
+        match self {
+            Transform::Rename { ref mut line, .. } |
+            Transform::Remove { ref mut line, .. } |
+            Transform::Copy { ref mut line, .. } |
+            Transform::Symlink { ref mut line, .. } |
+            Transform::Hardlink { ref mut line, .. } |
+            Transform::Chmod { ref mut line, .. } |
+            Transform::Chown { ref mut line, .. } |
+            Transform::Touch { ref mut line, .. } |
+            Transform::Create { ref mut line, .. } => *line = line_number
+        }
+
+        self
+    }
+
+    // New entries are created in `path', other transforms are left as they are
+    pub fn in_dir(mut self, path: &Path) -> Self {
+        if let Transform::Create { ref mut dir, .. } = self {
+            *dir = Some(path.to_path_buf());
+        }
+
+        self
+    }
+
+    pub fn hash_fragment(&self) -> Option<&str> {
+        Some(match *self {
             Transform::Rename { ref hash_fragment, .. } |
-            Transform::Remove { ref hash_fragment, .. } => hash_fragment
+            Transform::Remove { ref hash_fragment, .. } |
+            Transform::Copy { ref hash_fragment, .. } |
+            Transform::Symlink { ref hash_fragment, .. } |
+            Transform::Hardlink { ref hash_fragment, .. } |
+            Transform::Chmod { ref hash_fragment, .. } |
+            Transform::Chown { ref hash_fragment, .. } |
+            Transform::Touch { ref hash_fragment, .. } => hash_fragment,
+            Transform::Create { .. } => return None
+        })
+    }
+
+    pub fn line(&self) -> usize {
+        match *self {
+            Transform::Rename { line, .. } |
+            Transform::Remove { line, .. } |
+            Transform::Copy { line, .. } |
+            Transform::Symlink { line, .. } |
+            Transform::Hardlink { line, .. } |
+            Transform::Chmod { line, .. } |
+            Transform::Chown { line, .. } |
+            Transform::Touch { line, .. } |
+            Transform::Create { line, .. } => line
         }
     }
 }
@@ -128,17 +276,24 @@ impl Ord for Position {
 #[derive(Debug)]
 pub struct Parser {
     input: Vec<char>,
-    pos: Position
+    pos: Position,
+    columns: bool
 }
 
 impl Parser {
     pub fn new(input: &str) -> Self {
         Parser {
             input: input.chars().collect(),
-            pos: Position::new()
+            pos: Position::new(),
+            columns: false
         }
     }
 
+    // Whether lines have mode, owner, group and mtime columns between the hash and the pattern
+    pub fn columns(&mut self, columns: bool) {
+        self.columns = columns;
+    }
+
     pub fn reset(&mut self, input: &str) {
         self.input = input.chars().collect();
         self.pos = Position::new();
@@ -315,8 +470,122 @@ impl Parser {
         self.ignore_many0(&Parser::whitespace);
     }
 
+    fn pattern(&mut self) -> Result<String> {
+        let pos = self.position();
+
+        try!(self.ignore_many1(&|x: &mut Parser| x.one_of(" \t")).map_err(|e| {
+            Error::new_wrap("expected pattern", pos.clone(), e)
+        }));
+
+        match self.take_until_consume(&Parser::line_ending).trim() {
+            "" => Err(Error::new("expected pattern", pos)),
+            pattern => Ok(String::from(pattern))
+        }
+    }
+
+    // `+HASH PATTERN' copies the entry, `@HASH PATTERN' and `=HASH PATTERN'
+    // create a symbolic and a hard link to it respectively
+    fn duplicate(&mut self) -> Result<Transform> {
+        let marker = try!(self.one_of("+@="));
+        let hash_fragment = try!(self.hex_string());
+        let pattern = try!(self.pattern());
+
+        Ok(match marker {
+            '@' => Transform::symlink(hash_fragment, pattern),
+            '=' => Transform::hardlink(hash_fragment, pattern),
+            _ => Transform::copy(hash_fragment, pattern)
+        })
+    }
+
+    // `* PATTERN' creates an empty file, or a directory if the pattern ends
+    // with a slash; `* PATTERN < TEMPLATE' creates a copy of the template, where
+    // a leading `~' stands for the home directory
+    fn create(&mut self) -> Result<Transform> {
+        try!(self.char('*'));
+        let pattern = try!(self.pattern());
+
+        Ok(match pattern.rfind(" < ") {
+            Some(index) => Transform::create(String::from(pattern[..index].trim()),
+                                             Some(String::from(pattern[index + 3..].trim()))),
+            None => Transform::create(pattern, None)
+        })
+    }
+
+    // Lines starting with a marker rather than a hash
+    fn marked(&mut self) -> Result<Transform> {
+        self.either(&Parser::create, &Parser::duplicate)
+    }
+
+    fn column(&mut self) -> Result<String> {
+        let pos = self.position();
+
+        try!(self.ignore_many1(&|x: &mut Parser| x.one_of(" \t")).map_err(|e| {
+            Error::new_wrap("expected column", pos.clone(), e)
+        }));
+
+        self.many1(&|x: &mut Parser| x.satisfy(|c| !c.is_whitespace()))
+            .map(|vec| vec.into_iter().collect())
+            .map_err(|e| Error::new_wrap("expected column", pos, e))
+    }
+
+    fn mode(&mut self) -> Result<u32> {
+        let pos = self.position();
+        let column = try!(self.column());
+
+        if column.len() > 4 || !column.chars().all(|c| c.is_digit(8)) {
+            return Err(Error::new("expected octal mode", pos));
+        }
+
+        Ok(u32::from_str_radix(&column, 8).unwrap())
+    }
+
+    // `HASH MODE OWNER GROUP MTIME PATTERN', where any of the columns may be
+    // changed; a line with the hash alone removes the entry.
+    fn long_transform(&mut self) -> Result<Vec<Transform>> {
+        self.ignore_whitespace();
+        let line = self.pos.line;
+
+        if let Some(transform) = self.try_parser(&Parser::marked) {
+            return Ok(vec![transform.at(line)]);
+        }
+
+        let hash_fragment = try!(self.hex_string());
+
+        let end = self.try_parser(&|x: &mut Parser| {
+            x.ignore_many0(&|x: &mut Parser| x.one_of(" \t"));
+            x.line_ending()
+        });
+
+        if end.is_some() {
+            return Ok(vec![Transform::remove(hash_fragment).at(line)]);
+        }
+
+        let mode = try!(self.mode());
+        let owner = try!(self.column());
+        let group = try!(self.column());
+        let mtime = try!(self.column());
+
+        let pattern = self.take_until_consume(&Parser::line_ending);
+
+        let transforms = match pattern.trim() {
+            "" => vec![Transform::remove(hash_fragment)],
+            pattern => vec![Transform::rename(hash_fragment.clone(), String::from(pattern)),
+                            Transform::chmod(hash_fragment.clone(), mode),
+                            Transform::chown(hash_fragment.clone(), owner, group),
+                            Transform::touch(hash_fragment, mtime)]
+        };
+
+        Ok(transforms.into_iter().map(|transform| transform.at(line)).collect())
+    }
+
     fn transform(&mut self) -> Result<Transform> {
         self.ignore_whitespace();
+        let line = self.pos.line;
+
+        if let Some(transform) = self.try_parser(&Parser::marked) {
+            return Ok(transform.at(line));
+        }
+
         let hash_fragment = try!(self.hex_string());
 
         let space = self.try_parser(&|x: &mut Parser| {
@@ -326,7 +595,7 @@ impl Parser {
         if space.is_none() {
             let pos = self.position();
             return self.line_ending()
-                .map(|_| Transform::remove(hash_fragment))
+                .map(|_| Transform::remove(hash_fragment).at(line))
                 .map_err(|e| Error::new_wrap("expected pattern", pos, e));
         }
 
@@ -335,11 +604,16 @@ impl Parser {
         Ok(match pattern.trim() {
             "" => Transform::remove(hash_fragment),
             pattern => Transform::rename(hash_fragment, String::from(pattern))
-        })
+        }.at(line))
     }
 
     pub fn parse(&mut self) -> Result<Vec<Transform>> {
-        let transforms = self.many0(&Parser::transform);
+        let transforms = if self.columns {
+            self.many0(&Parser::long_transform).into_iter().flat_map(|line| line).collect()
+        } else {
+            self.many0(&Parser::transform)
+        };
+
         self.ignore_whitespace();
         let pos = self.position();
         try!(self.eof().map_err(|e| {
@@ -560,7 +834,8 @@ mod tests {
                                       # ^ this is an empty line\n\
                                       deadbeef /etc/secret");
         assert_eq!(Ok(Transform::Rename { hash_fragment: String::from("deadbeef"),
-                                          pattern: String::from("/etc/secret") }),
+                                          pattern: String::from("/etc/secret"),
+                                          line: 4 }),
                    parser.transform());
         assert!(parser.eof().is_ok());
 
@@ -568,14 +843,75 @@ mod tests {
                       \t   \n\
                       # ^ this is an empty line\n\
                       deadbeef ");
-        assert_eq!(Ok(Transform::Remove { hash_fragment: String::from("deadbeef")}), parser.transform());
+        assert_eq!(Ok(Transform::Remove { hash_fragment: String::from("deadbeef"), line: 4 }), parser.transform());
         assert!(parser.eof().is_ok());
 
         parser.reset("#  this is a comment\n\
                       \t   \n\
                       # ^ this is an empty line\n\
                       deadbeef");
-        assert_eq!(Ok(Transform::Remove { hash_fragment: String::from("deadbeef")}), parser.transform());
+        assert_eq!(Ok(Transform::Remove { hash_fragment: String::from("deadbeef"), line: 4 }), parser.transform());
+        assert!(parser.eof().is_ok());
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut parser = Parser::new("+deadbeef foo%n.bak\n");
+        assert_eq!(Ok(Transform::Copy { hash_fragment: String::from("deadbeef"),
+                                        pattern: String::from("foo%n.bak"),
+                                        line: 1 }),
+                   parser.transform());
         assert!(parser.eof().is_ok());
+
+        parser.reset("@deadbeef ../link\n=cafe hard");
+        assert_eq!(Ok(vec![Transform::symlink(String::from("deadbeef"), String::from("../link")).at(1),
+                           Transform::hardlink(String::from("cafe"), String::from("hard")).at(2)]),
+                   parser.parse());
+
+        parser.reset("+deadbeef \n");
+        assert!(parser.duplicate().is_err());
+        parser.reset("@deadbeef");
+        assert!(parser.duplicate().is_err());
+        parser.reset("= deadbeef foo");
+        assert!(parser.duplicate().is_err());
+    }
+
+    #[test]
+    fn long_transform() {
+        let mut parser = Parser::new("deadbeef 0755  root  wheel 2016-04-20T10:00:00 foo bar\n\
+                                      cafe\n\
+                                      +cafe copy");
+        parser.columns(true);
+
+        let hash = String::from("deadbeef");
+        assert_eq!(Ok(vec![Transform::rename(hash.clone(), String::from("foo bar")).at(1),
+                           Transform::chmod(hash.clone(), 0o755).at(1),
+                           Transform::chown(hash.clone(), String::from("root"), String::from("wheel")).at(1),
+                           Transform::touch(hash.clone(), String::from("2016-04-20T10:00:00")).at(1),
+                           Transform::remove(String::from("cafe")).at(2),
+                           Transform::copy(String::from("cafe"), String::from("copy")).at(3)]),
+                   parser.parse());
+
+        parser.reset("deadbeef 0644 root wheel 2016-04-20T10:00:00 \n");
+        assert_eq!(Ok(vec![Transform::remove(hash.clone()).at(1)]), parser.long_transform());
+
+        parser.reset("deadbeef 0648 root wheel 2016-04-20T10:00:00 foo");
+        assert!(parser.long_transform().is_err());
+        parser.reset("deadbeef 0644 root foo");
+        assert!(parser.long_transform().is_err());
+    }
+
+    #[test]
+    fn create() {
+        let mut parser = Parser::new("* notes.txt\n  * 2016/  \n* new %n < ~/templates/a < b\n");
+        assert_eq!(Ok(vec![Transform::create(String::from("notes.txt"), None).at(1),
+                           Transform::create(String::from("2016/"), None).at(2),
+                           Transform::create(String::from("new %n < ~/templates/a"), Some(String::from("b"))).at(3)]),
+                   parser.parse());
+
+        parser.reset("* \n");
+        assert!(parser.create().is_err());
+        parser.reset("*foo");
+        assert!(parser.create().is_err());
     }
 }
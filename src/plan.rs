@@ -0,0 +1,1061 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::{metadata, symlink_metadata};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::result;
+
+use util::{sha1};
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Operation {
+    Rename {
+        from: PathBuf,
+        to: PathBuf
+    },
+    Copy {
+        from: PathBuf,
+        to: PathBuf
+    },
+    Symlink {
+        target: PathBuf,
+        path: PathBuf
+    },
+    Hardlink {
+        from: PathBuf,
+        to: PathBuf
+    },
+    Remove {
+        path: PathBuf
+    },
+    // Creates an empty file
+    CreateFile {
+        path: PathBuf
+    },
+    Chmod {
+        path: PathBuf,
+        from: u32,
+        to: u32
+    },
+    // Owners are `(uid, gid)' pairs
+    Chown {
+        path: PathBuf,
+        from: (u32, u32),
+        to: (u32, u32)
+    },
+    // Modification times in seconds since the epoch
+    Touch {
+        path: PathBuf,
+        from: i64,
+        to: i64
+    },
+    CreateDir {
+        path: PathBuf
+    },
+    // Only removes an empty directory, used to revert `CreateDir`
+    RemoveDir {
+        path: PathBuf
+    }
+}
+
+impl Operation {
+    // Short name of the operation, the same one the journal uses
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Operation::Rename { .. } => "rename",
+            Operation::Copy { .. } => "copy",
+            Operation::Symlink { .. } => "symlink",
+            Operation::Hardlink { .. } => "hardlink",
+            Operation::Remove { .. } => "remove",
+            Operation::CreateFile { .. } => "create",
+            Operation::Chmod { .. } => "chmod",
+            Operation::Chown { .. } => "chown",
+            Operation::Touch { .. } => "touch",
+            Operation::CreateDir { .. } => "mkdir",
+            Operation::RemoveDir { .. } => "rmdir"
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+pub struct Problem {
+    line: usize,
+    desc: String
+}
+
+impl Problem {
+    pub fn new(line: usize, desc: String) -> Self {
+        Problem {
+            line: line,
+            desc: desc
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn desc(&self) -> &str {
+        &self.desc
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.desc)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    problems: Vec<Problem>
+}
+
+impl Error {
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "Invalid transforms, nothing has been changed:"));
+
+        for problem in &self.problems {
+            try!(write!(fmt, "\n    {}", problem));
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Invalid transforms"
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Kind {
+    Rename,
+    Copy,
+    Symlink,
+    Hardlink
+}
+
+// An operation which puts an entry, or a link to it, under a new path
+#[derive(Debug, Clone)]
+struct Transfer {
+    line: usize,
+    kind: Kind,
+    from: PathBuf,
+    to: PathBuf
+}
+
+#[derive(Debug, Clone)]
+struct Remove {
+    line: usize,
+    path: PathBuf
+}
+
+// A new empty file or directory
+#[derive(Debug, Clone)]
+struct Creation {
+    line: usize,
+    path: PathBuf,
+    dir: bool
+}
+
+#[derive(Debug, Clone)]
+enum Attribute {
+    Mode(u32, u32),
+    Owner((u32, u32), (u32, u32)),
+    Mtime(i64, i64)
+}
+
+// A change of attributes, applied once the entry has reached its place
+#[derive(Debug, Clone)]
+struct Change {
+    line: usize,
+    path: PathBuf,
+    attribute: Attribute
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+    transfers: Vec<Transfer>,
+    removals: Vec<Remove>,
+    creations: Vec<Creation>,
+    changes: Vec<Change>,
+    problems: Vec<Problem>,
+    parents: bool,
+    relative_links: bool,
+    overwrite: bool,
+    follow: bool
+}
+
+const TMP_PREFIX: &'static str = ".ded";
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn transfer(&mut self, line: usize, kind: Kind, from: PathBuf, to: PathBuf) {
+        self.transfers.push(Transfer { line: line, kind: kind, from: from, to: to });
+    }
+
+    pub fn rename(&mut self, line: usize, from: PathBuf, to: PathBuf) {
+        self.transfer(line, Kind::Rename, from, to);
+    }
+
+    pub fn copy(&mut self, line: usize, from: PathBuf, to: PathBuf) {
+        self.transfer(line, Kind::Copy, from, to);
+    }
+
+    pub fn symlink(&mut self, line: usize, from: PathBuf, to: PathBuf) {
+        self.transfer(line, Kind::Symlink, from, to);
+    }
+
+    pub fn hardlink(&mut self, line: usize, from: PathBuf, to: PathBuf) {
+        self.transfer(line, Kind::Hardlink, from, to);
+    }
+
+    pub fn remove(&mut self, line: usize, path: PathBuf) {
+        self.removals.push(Remove { line: line, path: path });
+    }
+
+    pub fn create_file(&mut self, line: usize, path: PathBuf) {
+        self.creations.push(Creation { line: line, path: path, dir: false });
+    }
+
+    pub fn create_dir(&mut self, line: usize, path: PathBuf) {
+        self.creations.push(Creation { line: line, path: path, dir: true });
+    }
+
+    fn change(&mut self, line: usize, path: PathBuf, attribute: Attribute) {
+        self.changes.push(Change { line: line, path: path, attribute: attribute });
+    }
+
+    pub fn chmod(&mut self, line: usize, path: PathBuf, from: u32, to: u32) {
+        if from != to {
+            self.change(line, path, Attribute::Mode(from, to));
+        }
+    }
+
+    pub fn chown(&mut self, line: usize, path: PathBuf, from: (u32, u32), to: (u32, u32)) {
+        if from != to {
+            self.change(line, path, Attribute::Owner(from, to));
+        }
+    }
+
+    pub fn touch(&mut self, line: usize, path: PathBuf, from: i64, to: i64) {
+        if from != to {
+            self.change(line, path, Attribute::Mtime(from, to));
+        }
+    }
+
+    // Whether missing parent directories of targets should be created
+    pub fn parents(&mut self, parents: bool) {
+        self.parents = parents;
+    }
+
+    // Whether symbolic links should point to their targets by a relative path
+    pub fn relative_links(&mut self, relative_links: bool) {
+        self.relative_links = relative_links;
+    }
+
+    // Whether targets may replace entries which already exist, otherwise they are reported
+    pub fn overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    // Whether symbolic links to directories are listed as directories, with entries beneath them
+    pub fn follow(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    pub fn problem(&mut self, line: usize, desc: String) {
+        self.problems.push(Problem::new(line, desc));
+    }
+
+    // Check the plan as a whole, reporting every problem found along with the
+    // ones registered while the plan was being built.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = self.problems.clone();
+        let mut sources: HashMap<&Path, usize> = HashMap::new();
+
+        let steps = self.transfers.iter()
+            .filter(|t| t.kind == Kind::Rename)
+            .map(|t| (t.line, &t.from))
+            .chain(self.removals.iter().map(|r| (r.line, &r.path)));
+
+        for (line, path) in steps {
+            if let Some(&first) = sources.get(path.as_path()) {
+                problems.push(Problem::new(line, format!("`{}' is already transformed on line {}",
+                                                         path.display(), first)));
+            } else {
+                sources.insert(path.as_path(), line);
+            }
+        }
+
+        let removed: HashMap<&Path, usize> = self.removals.iter().map(|r| (r.path.as_path(), r.line)).collect();
+
+        for change in &self.changes {
+            if let Some(line) = removed.get(change.path.as_path()) {
+                problems.push(Problem::new(change.line, format!("`{}' is removed on line {}",
+                                                                change.path.display(), line)));
+            }
+        }
+
+        // Directories which are moved or removed as a whole, their contents can't be changed separately
+        let moved: Vec<(&Path, usize, &str)> = self.transfers.iter()
+            .filter(|t| t.kind == Kind::Rename && t.from != t.to && self.is_dir(&t.from))
+            .map(|t| (t.from.as_path(), t.line, "moved"))
+            .chain(self.removals.iter().filter(|r| self.is_dir(&r.path)).map(|r| (r.path.as_path(), r.line, "removed")))
+            .collect();
+
+        let nested = self.transfers.iter()
+            .filter(|t| t.from != t.to)
+            .flat_map(|t| vec![(t.line, &t.from), (t.line, &t.to)])
+            .chain(self.removals.iter().map(|r| (r.line, &r.path)))
+            .chain(self.creations.iter().map(|c| (c.line, &c.path)))
+            .chain(self.changes.iter().map(|c| (c.line, &c.path)));
+
+        for (line, path) in nested {
+            let outer = moved.iter()
+                .find(|&&(dir, other_line, _)| other_line != line && path != dir && path.starts_with(dir));
+
+            if let Some(&(dir, other_line, what)) = outer {
+                problems.push(Problem::new(line, format!("`{}' is inside `{}', which is {} on line {}",
+                                                         path.display(), dir.display(), what, other_line)));
+            }
+        }
+
+        for copy in self.transfers.iter().filter(|t| t.kind != Kind::Rename) {
+            let verb = if copy.kind == Kind::Copy { "copy" } else { "link" };
+
+            if let Some(line) = removed.get(copy.from.as_path()) {
+                problems.push(Problem::new(copy.line, format!("`{}' is removed on line {}",
+                                                              copy.from.display(), line)));
+            } else if copy.from == copy.to {
+                problems.push(Problem::new(copy.line, format!("cannot {} `{}' onto itself",
+                                                              verb, copy.from.display())));
+            } else if copy.kind == Kind::Hardlink && self.is_dir(&copy.from) {
+                problems.push(Problem::new(copy.line, format!("cannot hard link directory `{}'",
+                                                              copy.from.display())));
+            }
+        }
+
+        // Entries left unchanged keep their names, so they collide like any other target
+        let mut targets: HashMap<&Path, (usize, bool)> = HashMap::new();
+
+        // Paths which are free once the plan is applied, other existing ones are not replaced
+        let vacated: HashSet<&Path> = self.transfers.iter()
+            .filter(|t| t.kind == Kind::Rename && t.from != t.to)
+            .map(|t| t.from.as_path())
+            .chain(self.removals.iter().map(|r| r.path.as_path()))
+            .collect();
+
+        let all_targets = self.transfers.iter()
+            .map(|t| (t.line, &t.to, t.from == t.to))
+            .chain(self.creations.iter().map(|c| (c.line, &c.path, false)));
+
+        for (line, target, kept) in all_targets {
+            if let Some(&(other_line, other_kept)) = targets.get(target.as_path()) {
+                let desc = if other_kept {
+                    format!("`{}' conflicts with the entry kept on line {}", target.display(), other_line)
+                } else if kept {
+                    format!("`{}' is kept, but it is the target of line {}", target.display(), other_line)
+                } else {
+                    format!("`{}' is also the target of line {}", target.display(), other_line)
+                };
+
+                problems.push(Problem::new(line, desc));
+                continue;
+            }
+
+            targets.insert(target, (line, kept));
+
+            // Anything on disk is in the way, a symlink pointing nowhere included
+            if !kept && !self.overwrite && !vacated.contains(target.as_path()) && symlink_metadata(target).is_ok() {
+                problems.push(Problem::new(line, format!("`{}' already exists", target.display())));
+            }
+        }
+
+        let created = self.created_dirs();
+
+        for transfer in &self.transfers {
+            if transfer.from == transfer.to {
+                continue;
+            }
+
+            let nests = transfer.kind == Kind::Rename || transfer.kind == Kind::Copy;
+
+            if nests && self.is_dir(&transfer.from) && transfer.to.starts_with(&transfer.from) {
+                problems.push(Problem::new(transfer.line, format!("cannot put `{}' into itself",
+                                                                  transfer.from.display())));
+            } else if let Some(problem) = self.check_parent(transfer.line, &transfer.to, &created) {
+                problems.push(problem);
+            }
+        }
+
+        for creation in &self.creations {
+            if let Some(problem) = self.check_parent(creation.line, &creation.path, &created) {
+                problems.push(problem);
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            problems.sort();
+            Err(Error { problems: problems })
+        }
+    }
+
+    // Symbolic links to directories are moved and removed by themselves, unless they are followed
+    fn is_dir(&self, path: &Path) -> bool {
+        let metadata = if self.follow { metadata(path) } else { symlink_metadata(path) };
+        metadata.map(|metadata| metadata.is_dir()).unwrap_or(false)
+    }
+
+    // The parent of `path` has to be a directory, either an existing or a created one
+    fn check_parent(&self, line: usize, path: &Path, created: &HashSet<&Path>) -> Option<Problem> {
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return None
+        };
+
+        let existing = if self.parents {
+            missing_ancestors(parent).last().map_or(Some(parent), |p| p.parent())
+        } else {
+            Some(parent)
+        };
+
+        match existing {
+            Some(existing) if !existing.is_dir() && !created.contains(existing) =>
+                Some(Problem::new(line, format!("directory `{}' does not exist", existing.display()))),
+            _ => None
+        }
+    }
+
+    fn created_dirs(&self) -> HashSet<&Path> {
+        self.creations.iter().filter(|c| c.dir).map(|c| c.path.as_path()).collect()
+    }
+
+    // Missing ancestors of targets along with the created directories;
+    // parents are ordered before their children
+    fn missing_dirs(&self) -> BTreeSet<PathBuf> {
+        let mut dirs: BTreeSet<_> = self.created_dirs().into_iter().map(Path::to_path_buf).collect();
+
+        if !self.parents {
+            return dirs;
+        }
+
+        let targets = self.transfers.iter()
+            .filter(|t| t.from != t.to)
+            .map(|t| &t.to)
+            .chain(self.creations.iter().map(|c| &c.path));
+
+        for target in targets {
+            let missing = target.parent().map_or(Vec::new(), missing_ancestors);
+
+            for dir in missing {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+
+        dirs
+    }
+
+    // Order planned operations so that they can be applied one by one: removals first, then
+    // anything whose target is no longer a pending source, breaking cycles through temporary
+    // names. Attributes are changed last, where their entries end up.
+    pub fn operations(&self) -> Vec<Operation> {
+        let mut operations: Vec<_> = self.removals.iter()
+            .map(|r| Operation::Remove { path: r.path.clone() })
+            .collect();
+
+        let destinations: HashMap<&Path, &Path> = self.transfers.iter()
+            .filter(|t| t.kind == Kind::Rename)
+            .map(|t| (t.from.as_path(), t.to.as_path()))
+            .collect();
+
+        let mut pending: Vec<_> = self.missing_dirs().into_iter()
+            .map(|path| Operation::CreateDir { path: path })
+            .collect();
+
+        pending.extend(self.transfers.iter()
+            .filter(|t| t.from != t.to)
+            .map(|t| {
+                let (from, to) = (t.from.clone(), t.to.clone());
+
+                match t.kind {
+                    Kind::Rename => Operation::Rename { from: from, to: to },
+                    Kind::Copy => Operation::Copy { from: from, to: to },
+                    Kind::Hardlink => Operation::Hardlink { from: from, to: to },
+                    Kind::Symlink => {
+                        let source = destinations.get(from.as_path()).map_or(from.as_path(), |d| *d);
+                        Operation::Symlink { target: self.link_target(source, &to), path: to }
+                    }
+                }
+            }));
+
+        pending.extend(self.creations.iter()
+            .filter(|c| !c.dir)
+            .map(|c| Operation::CreateFile { path: c.path.clone() }));
+
+        let mut taken: HashSet<PathBuf> = HashSet::new();
+
+        for transfer in &self.transfers {
+            taken.insert(transfer.from.clone());
+            taken.insert(transfer.to.clone());
+        }
+
+        for creation in &self.creations {
+            taken.insert(creation.path.clone());
+        }
+
+        while !pending.is_empty() {
+            let ready = {
+                let mut moved = HashSet::new();
+                let mut copied = HashSet::new();
+                let mut dirs = HashSet::new();
+
+                for operation in &pending {
+                    match *operation {
+                        Operation::Rename { ref from, .. } => moved.insert(from.as_path()),
+                        Operation::Copy { ref from, .. } |
+                        Operation::Hardlink { ref from, .. } => copied.insert(from.as_path()),
+                        Operation::CreateDir { ref path } => dirs.insert(path.as_path()),
+                        _ => false
+                    };
+                }
+
+                let blocked = |path: &Path| moved.contains(path) || within(path, &dirs);
+
+                pending.iter().position(|operation| match *operation {
+                    Operation::Rename { ref from, ref to } =>
+                        !blocked(to) && !copied.contains(from.as_path()) && !copied.contains(to.as_path()),
+                    Operation::Copy { ref to, .. } |
+                    Operation::Hardlink { ref to, .. } |
+                    Operation::Symlink { path: ref to, .. } |
+                    Operation::CreateFile { path: ref to } |
+                    Operation::CreateDir { path: ref to } => !blocked(to),
+                    _ => true
+                })
+            };
+
+            if let Some(index) = ready {
+                operations.push(pending.remove(index));
+                continue;
+            }
+
+            // Every pending target is a pending source, i.e. only cycles are left
+            let cycle = pending.iter_mut().filter_map(|operation| match *operation {
+                Operation::Rename { ref mut from, .. } => Some(from),
+                _ => None
+            }).next().map(|from| {
+                let tmp = temp_path(from, &taken);
+                (mem::replace(from, tmp.clone()), tmp)
+            });
+
+            if let Some((from, tmp)) = cycle {
+                for operation in &mut pending {
+                    match *operation {
+                        Operation::Copy { from: ref mut source, .. } |
+                        Operation::Hardlink { from: ref mut source, .. } if *source == from => *source = tmp.clone(),
+                        _ => {}
+                    }
+                }
+
+                taken.insert(tmp.clone());
+                operations.push(Operation::Rename { from: from, to: tmp });
+            } else {
+                // Only renames can be blocked by each other, so this is not supposed to happen
+                operations.extend(pending.drain(..));
+            }
+        }
+
+        for change in &self.changes {
+            let path = destinations.get(change.path.as_path()).map_or(change.path.clone(), |d| d.to_path_buf());
+
+            operations.push(match change.attribute {
+                Attribute::Mode(from, to) => Operation::Chmod { path: path, from: from, to: to },
+                Attribute::Owner(from, to) => Operation::Chown { path: path, from: from, to: to },
+                Attribute::Mtime(from, to) => Operation::Touch { path: path, from: from, to: to }
+            });
+        }
+
+        operations
+    }
+
+    fn link_target(&self, target: &Path, link: &Path) -> PathBuf {
+        match link.parent() {
+            Some(dir) if self.relative_links => relative_path(target, dir),
+            _ => target.to_path_buf()
+        }
+    }
+}
+
+// Path to `target` relative to the directory `dir`, both absolute
+fn relative_path(target: &Path, dir: &Path) -> PathBuf {
+    let mut target_components = target.components().peekable();
+    let mut dir_components = dir.components().peekable();
+
+    while target_components.peek().is_some() && target_components.peek() == dir_components.peek() {
+        target_components.next();
+        dir_components.next();
+    }
+
+    let mut path: PathBuf = dir_components.map(|_| "..").collect();
+
+    for component in target_components {
+        path.push(component.as_os_str());
+    }
+
+    path
+}
+
+// Tracks paths freed or taken by already applied operations, so that
+// conflicts can be detected without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct Layout {
+    vacated: HashSet<PathBuf>,
+    occupied: HashSet<PathBuf>
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        // Dangling symlinks take their path as well
+        self.occupied.contains(path) || (symlink_metadata(path).is_ok() && !self.vacated.contains(path))
+    }
+
+    pub fn occupied(&self) -> &HashSet<PathBuf> {
+        &self.occupied
+    }
+
+    pub fn vacate(&mut self, path: &Path) {
+        self.occupied.remove(path);
+        self.vacated.insert(path.to_path_buf());
+    }
+
+    pub fn occupy(&mut self, path: &Path) {
+        self.vacated.remove(path);
+        self.occupied.insert(path.to_path_buf());
+    }
+}
+
+// Whether `path` lies inside any of `dirs`
+fn within(path: &Path, dirs: &HashSet<&Path>) -> bool {
+    let mut current = path.parent();
+
+    while let Some(dir) = current {
+        if dirs.contains(dir) {
+            return true;
+        }
+
+        current = dir.parent();
+    }
+
+    false
+}
+
+// `path` and its ancestors which do not exist, innermost first
+fn missing_ancestors(path: &Path) -> Vec<&Path> {
+    let mut missing = Vec::new();
+    let mut current = Some(path);
+
+    while let Some(path) = current {
+        if path.as_os_str().is_empty() || path.exists() {
+            break;
+        }
+
+        missing.push(path);
+        current = path.parent();
+    }
+
+    missing
+}
+
+// Find an unused name next to `path` to temporarily move it to
+pub fn temp_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let hash = sha1(&path.to_string_lossy());
+    let mut counter = 0;
+
+    loop {
+        let candidate = parent.join(format!("{}-{}.{}", TMP_PREFIX, &hash[0..8], counter));
+
+        if !taken.contains(&candidate) && symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, File};
+    use std::os::unix::fs::{symlink};
+    use std::path::{Path, PathBuf};
+
+    use tempdir::{TempDir};
+
+    use super::*;
+
+    fn rename(from: &str, to: &str) -> Operation {
+        Operation::Rename { from: PathBuf::from(from), to: PathBuf::from(to) }
+    }
+
+    // Apply renames to a list of names to see where each one ends up
+    fn simulate(names: &[&str], operations: &[Operation]) -> Vec<(String, String)> {
+        let mut state: Vec<_> = names.iter()
+            .map(|name| (String::from(*name), PathBuf::from(name)))
+            .collect();
+
+        for operation in operations {
+            if let Operation::Rename { ref from, ref to } = *operation {
+                assert!(state.iter().all(|&(_, ref path)| path != to), "overwrites {:?}", to);
+
+                for &mut (_, ref mut path) in &mut state {
+                    if path == from {
+                        *path = to.clone();
+                    }
+                }
+            }
+        }
+
+        state.into_iter()
+            .map(|(name, path)| (name, path.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn chain() {
+        let mut plan = Plan::new();
+        plan.rename(0, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.rename(0, PathBuf::from("/ded-plan/b"), PathBuf::from("/ded-plan/c"));
+
+        assert_eq!(vec![rename("/ded-plan/b", "/ded-plan/c"), rename("/ded-plan/a", "/ded-plan/b")],
+                   plan.operations());
+    }
+
+    #[test]
+    fn swap() {
+        let mut plan = Plan::new();
+        plan.rename(0, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.rename(0, PathBuf::from("/ded-plan/b"), PathBuf::from("/ded-plan/a"));
+
+        let operations = plan.operations();
+        assert_eq!(3, operations.len());
+        assert_eq!(vec![(String::from("/ded-plan/a"), String::from("/ded-plan/b")),
+                        (String::from("/ded-plan/b"), String::from("/ded-plan/a"))],
+                   simulate(&["/ded-plan/a", "/ded-plan/b"], &operations));
+    }
+
+    #[test]
+    fn rotation() {
+        let mut plan = Plan::new();
+        plan.rename(0, PathBuf::from("/ded-plan/1"), PathBuf::from("/ded-plan/2"));
+        plan.rename(0, PathBuf::from("/ded-plan/2"), PathBuf::from("/ded-plan/3"));
+        plan.rename(0, PathBuf::from("/ded-plan/3"), PathBuf::from("/ded-plan/1"));
+        plan.rename(0, PathBuf::from("/ded-plan/4"), PathBuf::from("/ded-plan/4"));
+
+        let operations = plan.operations();
+        assert_eq!(4, operations.len());
+        assert_eq!(vec![(String::from("/ded-plan/1"), String::from("/ded-plan/2")),
+                        (String::from("/ded-plan/2"), String::from("/ded-plan/3")),
+                        (String::from("/ded-plan/3"), String::from("/ded-plan/1"))],
+                   simulate(&["/ded-plan/1", "/ded-plan/2", "/ded-plan/3"], &operations));
+    }
+
+    #[test]
+    fn removals_first() {
+        let mut plan = Plan::new();
+        plan.rename(0, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.remove(0, PathBuf::from("/ded-plan/b"));
+
+        assert_eq!(vec![Operation::Remove { path: PathBuf::from("/ded-plan/b") },
+                        rename("/ded-plan/a", "/ded-plan/b")],
+                   plan.operations());
+    }
+
+    #[test]
+    fn copies() {
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/a"), PathBuf::from("/b"));
+        plan.rename(2, PathBuf::from("/b"), PathBuf::from("/c"));
+        plan.copy(3, PathBuf::from("/a"), PathBuf::from("/d"));
+        plan.copy(4, PathBuf::from("/b"), PathBuf::from("/e"));
+        assert!(plan.validate().is_ok());
+
+        // Sources are copied before they are renamed away
+        assert_eq!(vec![Operation::Copy { from: PathBuf::from("/a"), to: PathBuf::from("/d") },
+                        Operation::Copy { from: PathBuf::from("/b"), to: PathBuf::from("/e") },
+                        rename("/b", "/c"),
+                        rename("/a", "/b")],
+                   plan.operations());
+
+        plan.copy(5, PathBuf::from("/f"), PathBuf::from("/f"));
+        plan.copy(6, PathBuf::from("/g"), PathBuf::from("/d"));
+        plan.remove(7, PathBuf::from("/a"));
+
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![3, 5, 6, 7], lines);
+    }
+
+    #[test]
+    fn copy_sources() {
+        // The target of a rename is copied before it is replaced
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/b"), PathBuf::from("/a"));
+        plan.copy(2, PathBuf::from("/a"), PathBuf::from("/c"));
+        plan.hardlink(3, PathBuf::from("/a"), PathBuf::from("/d"));
+
+        assert_eq!(vec![Operation::Copy { from: PathBuf::from("/a"), to: PathBuf::from("/c") },
+                        Operation::Hardlink { from: PathBuf::from("/a"), to: PathBuf::from("/d") },
+                        rename("/b", "/a")],
+                   plan.operations());
+
+        // A source moved aside to break a cycle is copied from its temporary name
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.copy(2, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/c"));
+        plan.rename(3, PathBuf::from("/ded-plan/c"), PathBuf::from("/ded-plan/a"));
+
+        let operations = plan.operations();
+        let tmp = match operations[0] {
+            Operation::Rename { ref from, ref to } if from == Path::new("/ded-plan/a") => to.clone(),
+            ref operation => panic!("unexpected operation: {:?}", operation)
+        };
+
+        assert_eq!(vec![rename("/ded-plan/c", "/ded-plan/a"),
+                        Operation::Copy { from: tmp.clone(), to: PathBuf::from("/ded-plan/c") },
+                        Operation::Rename { from: tmp.clone(), to: PathBuf::from("/ded-plan/b") }],
+                   &operations[1..]);
+    }
+
+    #[test]
+    fn links() {
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.rename(2, PathBuf::from("/ded-plan/b"), PathBuf::from("/ded-plan/c"));
+        plan.symlink(3, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/d"));
+        plan.hardlink(4, PathBuf::from("/ded-plan/b"), PathBuf::from("/ded-plan/e"));
+        plan.relative_links(true);
+
+        assert_eq!(vec![Operation::Symlink { target: PathBuf::from("b"), path: PathBuf::from("/ded-plan/d") },
+                        Operation::Hardlink { from: PathBuf::from("/ded-plan/b"), to: PathBuf::from("/ded-plan/e") },
+                        rename("/ded-plan/b", "/ded-plan/c"),
+                        rename("/ded-plan/a", "/ded-plan/b")],
+                   plan.operations());
+
+        assert_eq!(PathBuf::from("../../c/d"), relative_path(Path::new("/a/c/d"), Path::new("/a/b/e")));
+        assert_eq!(PathBuf::from("c"), relative_path(Path::new("/a/b/c"), Path::new("/a/b")));
+        assert_eq!(PathBuf::from(".."), relative_path(Path::new("/a"), Path::new("/a/b")));
+    }
+
+    #[test]
+    fn changes() {
+        let mut plan = Plan::new();
+        plan.chmod(1, PathBuf::from("/ded-plan/a"), 0o644, 0o600);
+        plan.rename(1, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/b"));
+        plan.chown(2, PathBuf::from("/ded-plan/c"), (0, 0), (0, 0));
+        plan.touch(2, PathBuf::from("/ded-plan/c"), 0, 1461146400);
+
+        assert_eq!(vec![rename("/ded-plan/a", "/ded-plan/b"),
+                        Operation::Chmod { path: PathBuf::from("/ded-plan/b"), from: 0o644, to: 0o600 },
+                        Operation::Touch { path: PathBuf::from("/ded-plan/c"), from: 0, to: 1461146400 }],
+                   plan.operations());
+
+        plan.remove(3, PathBuf::from("/ded-plan/c"));
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert!(lines.contains(&2));
+    }
+
+    #[test]
+    fn creations() {
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/ded-plan/a"), PathBuf::from("/ded-plan/new/a"));
+        plan.create_file(2, PathBuf::from("/ded-plan/a"));
+        plan.create_dir(3, PathBuf::from("/ded-plan/new"));
+        plan.create_file(4, PathBuf::from("/ded-plan/new/b"));
+
+        assert_eq!(vec![Operation::CreateDir { path: PathBuf::from("/ded-plan/new") },
+                        rename("/ded-plan/a", "/ded-plan/new/a"),
+                        Operation::CreateFile { path: PathBuf::from("/ded-plan/a") },
+                        Operation::CreateFile { path: PathBuf::from("/ded-plan/new/b") }],
+                   plan.operations());
+
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let mut plan = Plan::new();
+        plan.create_dir(1, tmp.path().join("a"));
+        plan.create_file(2, tmp.path().join("a").join("b"));
+        plan.create_file(3, tmp.path().join("c").join("d"));
+        plan.create_file(4, tmp.path().join("a"));
+
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![3, 4], lines);
+    }
+
+    #[test]
+    fn validate() {
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let (a, b, c) = (tmp.path().join("a"), tmp.path().join("b"), tmp.path().join("c"));
+        create_dir(&a).unwrap();
+
+        let mut plan = Plan::new();
+        plan.rename(1, a.clone(), b.clone());
+        plan.rename(2, c.clone(), c.clone());
+        assert!(plan.validate().is_ok());
+
+        plan.remove(3, a.clone());
+        plan.rename(4, c.clone(), a.join("c"));
+        plan.rename(5, b.clone(), tmp.path().join("missing").join("b"));
+        plan.problem(0, String::from("bad line"));
+
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![0, 3, 4, 4, 5], lines);
+
+        let mut plan = Plan::new();
+        plan.rename(1, a.clone(), a.join("b"));
+        assert!(plan.validate().is_err());
+    }
+
+    #[test]
+    fn collisions() {
+        let mut plan = Plan::new();
+        plan.rename(1, PathBuf::from("/a"), PathBuf::from("/c"));
+        plan.rename(2, PathBuf::from("/b"), PathBuf::from("/c"));
+        plan.rename(3, PathBuf::from("/d"), PathBuf::from("/d"));
+        plan.rename(4, PathBuf::from("/e"), PathBuf::from("/d"));
+        plan.rename(5, PathBuf::from("/f"), PathBuf::from("/g"));
+        plan.remove(6, PathBuf::from("/g"));
+        plan.rename(7, PathBuf::from("/h"), PathBuf::from("/i"));
+        plan.rename(8, PathBuf::from("/i"), PathBuf::from("/h"));
+        plan.rename(9, PathBuf::from("/j"), PathBuf::from("/k"));
+        plan.rename(10, PathBuf::from("/k"), PathBuf::from("/k"));
+
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![2, 4, 10], lines);
+    }
+
+    #[test]
+    fn existing() {
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let path = |name: &str| tmp.path().join(name);
+
+        for name in &["a", "b", "c", "d"] {
+            File::create(path(name)).unwrap();
+        }
+
+        create_dir(path("dir")).unwrap();
+        symlink("dir", path("link")).unwrap();
+        symlink("missing", path("broken")).unwrap();
+
+        // Targets which are renamed away or removed first are free
+        let mut plan = Plan::new();
+        plan.rename(1, path("a"), path("b"));
+        plan.rename(2, path("b"), path("c"));
+        plan.remove(3, path("c"));
+        plan.rename(4, path("d"), path("d"));
+        plan.rename(5, path("link"), path("e"));
+        plan.rename(6, path("link/x"), path("link/x"));
+        assert!(plan.validate().is_ok());
+
+        plan.copy(7, path("d"), path("a"));
+        plan.create_file(8, path("dir"));
+        plan.symlink(9, path("d"), path("broken"));
+
+        let lines: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![8, 9], lines);
+
+        plan.overwrite(true);
+        assert!(plan.validate().is_ok());
+    }
+
+    #[test]
+    fn temp_paths() {
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let path = tmp.path().join("a");
+        let first = temp_path(&path, &HashSet::new());
+
+        // A dangling symlink takes the name as well
+        symlink("missing", &first).unwrap();
+        let second = temp_path(&path, &HashSet::new());
+        assert!(second != first);
+
+        let taken = vec![second.clone()].into_iter().collect();
+        assert!(temp_path(&path, &taken) != second);
+    }
+
+    #[test]
+    fn nested() {
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let path = |name: &str| tmp.path().join(name);
+
+        create_dir(path("a")).unwrap();
+        create_dir(path("b")).unwrap();
+        File::create(path("a/x")).unwrap();
+        File::create(path("b/y")).unwrap();
+
+        // Entries inside a moved directory may be kept, or moved into a directory which stays
+        let mut plan = Plan::new();
+        plan.rename(1, path("a"), path("c"));
+        plan.rename(2, path("a/x"), path("a/x"));
+        plan.rename(3, path("b/y"), path("z"));
+        assert!(plan.validate().is_ok());
+
+        plan.rename(4, path("z"), path("a/z"));
+        plan.remove(5, path("b"));
+
+        let problems: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![3, 4], problems);
+
+        // Entries beneath a followed symlink go along with it
+        symlink("b", path("link")).unwrap();
+
+        let mut plan = Plan::new();
+        plan.rename(1, path("link"), path("l"));
+        plan.rename(2, path("link/y"), path("y"));
+        assert!(plan.validate().is_ok());
+
+        plan.follow(true);
+        let problems: Vec<_> = plan.validate().unwrap_err().problems().iter().map(|p| p.line).collect();
+        assert_eq!(vec![2], problems);
+    }
+
+    #[test]
+    fn parents() {
+        let tmp = TempDir::new("ded-plan").unwrap();
+        let a = tmp.path().join("a");
+        let nested = tmp.path().join("2016").join("04");
+
+        let mut plan = Plan::new();
+        plan.rename(1, a.clone(), nested.join("a"));
+        plan.rename(2, tmp.path().join("b"), nested.join("b"));
+        assert!(plan.validate().is_err());
+
+        plan.parents(true);
+        assert!(plan.validate().is_ok());
+        assert_eq!(vec![Operation::CreateDir { path: tmp.path().join("2016") },
+                        Operation::CreateDir { path: nested.clone() },
+                        Operation::Rename { from: a.clone(), to: nested.join("a") },
+                        Operation::Rename { from: tmp.path().join("b"), to: nested.join("b") }],
+                   plan.operations());
+
+        File::create(tmp.path().join("file")).unwrap();
+        plan.rename(3, tmp.path().join("c"), tmp.path().join("file").join("dir").join("c"));
+        assert!(plan.validate().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap};
+use std::io::{self, Write};
+use std::path::{Path};
+
+use rustc_serialize::json::{self, Json, ToJson};
+
+use plan::{Operation, Problem};
+
+// What became of a planned operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Done,
+    Skipped,
+    Failed(String),
+    RolledBack
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match *self {
+            Status::Done => "done",
+            Status::Skipped => "skipped",
+            Status::Failed(_) => "failed",
+            Status::RolledBack => "rolled back"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub operation: Operation,
+    pub status: Status
+}
+
+impl Record {
+    pub fn new(operation: Operation, status: Status) -> Self {
+        Record {
+            operation: operation,
+            status: status
+        }
+    }
+
+    // Source and target of the operation, either may be missing
+    fn paths(&self) -> (Option<&Path>, Option<&Path>) {
+        match self.operation {
+            Operation::Rename { ref from, ref to } |
+            Operation::Copy { ref from, ref to } |
+            Operation::Hardlink { ref from, ref to } => (Some(from), Some(to)),
+            Operation::Symlink { ref target, ref path } => (Some(target), Some(path)),
+            Operation::Remove { ref path } |
+            Operation::RemoveDir { ref path } => (Some(path), None),
+            Operation::CreateFile { ref path } |
+            Operation::CreateDir { ref path } => (None, Some(path)),
+            Operation::Chmod { ref path, .. } |
+            Operation::Chown { ref path, .. } |
+            Operation::Touch { ref path, .. } => (Some(path), Some(path))
+        }
+    }
+}
+
+fn path_json(path: Option<&Path>) -> Json {
+    path.map_or(Json::Null, |path| Json::String(path.to_string_lossy().into_owned()))
+}
+
+impl ToJson for Record {
+    fn to_json(&self) -> Json {
+        let (source, target) = self.paths();
+        let error = match self.status {
+            Status::Failed(ref e) => Json::String(e.clone()),
+            _ => Json::Null
+        };
+
+        let mut object = BTreeMap::new();
+        object.insert(String::from("kind"), self.operation.kind().to_json());
+        object.insert(String::from("source"), path_json(source));
+        object.insert(String::from("target"), path_json(target));
+        object.insert(String::from("status"), self.status.name().to_json());
+        object.insert(String::from("error"), error);
+
+        Json::Object(object)
+    }
+}
+
+fn problem_json(problem: &Problem) -> Json {
+    let mut object = BTreeMap::new();
+    object.insert(String::from("line"), problem.line().to_json());
+    object.insert(String::from("error"), problem.desc().to_json());
+
+    Json::Object(object)
+}
+
+// The whole report is a single JSON object: the directories, whether it was a dry run,
+// and a record for each planned operation in the order they were scheduled. A plan
+// which doesn't pass validation is `rejected', with its problems instead of operations.
+pub fn write_json<W: Write>(w: &mut W, dirs: &[&Path], dry_run: bool,
+                            records: &[Record], problems: &[Problem]) -> io::Result<()> {
+    let status = if problems.is_empty() { "accepted" } else { "rejected" };
+
+    let mut object = BTreeMap::new();
+    object.insert(String::from("dirs"), Json::Array(dirs.iter().map(|dir| path_json(Some(dir))).collect()));
+    object.insert(String::from("dry_run"), dry_run.to_json());
+    object.insert(String::from("status"), status.to_json());
+    object.insert(String::from("operations"), Json::Array(records.iter().map(ToJson::to_json).collect()));
+    object.insert(String::from("problems"), Json::Array(problems.iter().map(problem_json).collect()));
+
+    writeln!(w, "{}", json::as_pretty_json(&Json::Object(object)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use rustc_serialize::json::{Json};
+
+    use plan::{Operation, Problem};
+    use super::*;
+
+    #[test]
+    fn json() {
+        let records = vec![
+            Record::new(Operation::Rename { from: PathBuf::from("/tmp/a\"b"), to: PathBuf::from("/tmp/c") },
+                        Status::Done),
+            Record::new(Operation::Remove { path: PathBuf::from("/tmp/d") }, Status::Failed(String::from("oops"))),
+            Record::new(Operation::CreateDir { path: PathBuf::from("/tmp/e") }, Status::Skipped),
+        ];
+
+        let mut buf = Vec::new();
+        write_json(&mut buf, &[Path::new("/tmp"), Path::new("/var")], false, &records, &[]).unwrap();
+
+        let report = Json::from_str(&String::from_utf8(buf).unwrap()).unwrap();
+        assert_eq!(Some("/var"), report["dirs"][1].as_string());
+        assert_eq!(Some(false), report["dry_run"].as_boolean());
+        assert_eq!(Some("accepted"), report["status"].as_string());
+        assert_eq!(Some(0), report["problems"].as_array().map(|problems| problems.len()));
+
+        let operations = report["operations"].as_array().unwrap();
+        assert_eq!(3, operations.len());
+
+        assert_eq!(Some("rename"), operations[0]["kind"].as_string());
+        assert_eq!(Some("/tmp/a\"b"), operations[0]["source"].as_string());
+        assert_eq!(Some("/tmp/c"), operations[0]["target"].as_string());
+        assert_eq!(Some("done"), operations[0]["status"].as_string());
+        assert!(operations[0]["error"].is_null());
+
+        assert!(operations[1]["target"].is_null());
+        assert_eq!(Some("failed"), operations[1]["status"].as_string());
+        assert_eq!(Some("oops"), operations[1]["error"].as_string());
+
+        assert!(operations[2]["source"].is_null());
+        assert_eq!(Some("skipped"), operations[2]["status"].as_string());
+
+        let problems = vec![Problem::new(3, String::from("`/tmp/c' already exists"))];
+        let mut buf = Vec::new();
+        write_json(&mut buf, &[Path::new("/tmp")], false, &[], &problems).unwrap();
+
+        let report = Json::from_str(&String::from_utf8(buf).unwrap()).unwrap();
+        assert_eq!(Some("rejected"), report["status"].as_string());
+        assert_eq!(Some(3), report["problems"][0]["line"].as_u64());
+        assert_eq!(Some("`/tmp/c' already exists"), report["problems"][0]["error"].as_string());
+    }
+}
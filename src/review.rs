@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use attr;
+use plan::{Layout, Operation};
+
+// A planned change as it is shown for review. Chains of renames through temporary
+// names are folded, so each moved entry appears once with its final name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: &'static str,
+    pub description: String,
+    pub overwrites: bool
+}
+
+impl Change {
+    // Whether anything is lost by the change
+    pub fn destructive(&self) -> bool {
+        self.overwrites || self.kind == "remove" || self.kind == "rmdir"
+    }
+}
+
+fn relative(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).to_string_lossy().into_owned()
+}
+
+fn dir_name(dir: &Path, path: &Path) -> String {
+    format!("{}/", relative(dir, path))
+}
+
+pub fn changes(dir: &Path, operations: &[Operation]) -> Vec<Change> {
+    let mut layout = Layout::new();
+    let mut changes: Vec<Change> = Vec::new();
+    // Index of a rename in `changes`, with its original source and current destination
+    let mut renames: Vec<(usize, PathBuf, PathBuf)> = Vec::new();
+
+    for operation in operations {
+        let (description, overwrites) = match *operation {
+            Operation::Rename { ref from, ref to } => {
+                let overwrites = layout.exists(to);
+                layout.vacate(from);
+                layout.occupy(to);
+
+                // Continue a chain started by an earlier rename
+                if let Some(&mut (index, ref original, ref mut current)) =
+                    renames.iter_mut().find(|&&mut (_, _, ref current)| current == from) {
+                    *current = to.clone();
+
+                    let change = &mut changes[index];
+                    change.description = format!("{} -> {}", relative(dir, original), relative(dir, to));
+                    change.overwrites = change.overwrites || overwrites;
+                    continue;
+                }
+
+                renames.push((changes.len(), from.clone(), to.clone()));
+                (format!("{} -> {}", relative(dir, from), relative(dir, to)), overwrites)
+            },
+            Operation::Copy { ref from, ref to } |
+            Operation::Hardlink { ref from, ref to } => {
+                let overwrites = layout.exists(to);
+                layout.occupy(to);
+                (format!("{} -> {}", relative(dir, from), relative(dir, to)), overwrites)
+            },
+            Operation::Symlink { ref target, ref path } => {
+                let overwrites = layout.exists(path);
+                layout.occupy(path);
+                (format!("{} -> {}", relative(dir, path), target.display()), overwrites)
+            },
+            Operation::CreateFile { ref path } => {
+                let overwrites = layout.exists(path);
+                layout.occupy(path);
+                (relative(dir, path), overwrites)
+            },
+            Operation::Remove { ref path } => {
+                layout.vacate(path);
+                (relative(dir, path), false)
+            },
+            Operation::CreateDir { ref path } => {
+                layout.occupy(path);
+                (dir_name(dir, path), false)
+            },
+            Operation::RemoveDir { ref path } => {
+                layout.vacate(path);
+                (dir_name(dir, path), false)
+            },
+            Operation::Chmod { ref path, from, to } =>
+                (format!("{}: {} -> {}", relative(dir, path), attr::format_mode(from), attr::format_mode(to)), false),
+            Operation::Chown { ref path, from, to } =>
+                (format!("{}: {}:{} -> {}:{}", relative(dir, path),
+                         attr::user_name(from.0), attr::group_name(from.1),
+                         attr::user_name(to.0), attr::group_name(to.1)), false),
+            Operation::Touch { ref path, from, to } =>
+                (format!("{}: {} -> {}", relative(dir, path), attr::format_mtime(from), attr::format_mtime(to)), false)
+        };
+
+        changes.push(Change {
+            kind: operation.kind(),
+            description: description,
+            overwrites: overwrites
+        });
+    }
+
+    changes
+}
+
+// Number of changes of each kind, in the order they first appear
+pub fn counts(changes: &[Change]) -> Vec<(&'static str, usize)> {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+
+    for change in changes {
+        match counts.iter().position(|&(kind, _)| kind == change.kind) {
+            Some(index) => counts[index].1 += 1,
+            None => counts.push((change.kind, 1))
+        }
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File};
+    use std::path::{PathBuf};
+
+    use tempdir::{TempDir};
+
+    use plan::{Operation};
+    use super::*;
+
+    #[test]
+    fn changes_and_counts() {
+        let tmp = TempDir::new("ded-review").unwrap();
+        let dir = tmp.path();
+        let path = |name: &str| dir.join(name);
+
+        for name in &["a", "b", "c", "d"] {
+            File::create(path(name)).unwrap();
+        }
+
+        let operations = vec![
+            Operation::Remove { path: path("d") },
+            Operation::Rename { from: path("a"), to: path(".ded-0") },
+            Operation::Rename { from: path("b"), to: path("a") },
+            Operation::Rename { from: path(".ded-0"), to: path("b") },
+            Operation::CreateDir { path: path("e") },
+            Operation::Copy { from: path("a"), to: path("c") },
+            Operation::Symlink { target: PathBuf::from("../a"), path: path("e/a") },
+        ];
+
+        let changes = changes(dir, &operations);
+        let descriptions: Vec<_> = changes.iter().map(|c| (c.kind, &c.description[..], c.overwrites)).collect();
+
+        assert_eq!(vec![("remove", "d", false),
+                        ("rename", "a -> b", false),
+                        ("rename", "b -> a", false),
+                        ("mkdir", "e/", false),
+                        ("copy", "a -> c", true),
+                        ("symlink", "e/a -> ../a", false)],
+                   descriptions);
+
+        assert!(changes[0].destructive());
+        assert!(!changes[1].destructive());
+        assert!(changes[4].destructive());
+
+        assert_eq!(vec![("remove", 1), ("rename", 2), ("mkdir", 1), ("copy", 1), ("symlink", 1)],
+                   counts(&changes));
+    }
+}
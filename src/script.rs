@@ -0,0 +1,220 @@
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt};
+use std::path::{Path};
+use std::str;
+
+use attr;
+use plan::{Layout, Operation};
+
+// Quote a word for the shell, single quotes inside are closed, escaped and reopened
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Same as `quote', bytes which aren't valid UTF-8 are written by `printf' as octal
+// escapes in between, since not every `sh' understands `$'\xNN''
+fn quote_bytes(mut bytes: &[u8]) -> String {
+    let mut buf = String::new();
+
+    loop {
+        let valid_up_to = match str::from_utf8(bytes) {
+            Ok(s) => {
+                if buf.is_empty() || !s.is_empty() {
+                    buf.push_str(&quote(s));
+                }
+                return buf;
+            },
+            Err(e) => e.valid_up_to()
+        };
+
+        let (valid, rest) = bytes.split_at(valid_up_to);
+
+        if !valid.is_empty() {
+            buf.push_str(&quote(str::from_utf8(valid).unwrap_or("")));
+        }
+
+        buf.push_str(&format!("\"$(printf '\\{:03o}')\"", rest[0]));
+        bytes = &rest[1..];
+    }
+}
+
+fn quote_path(dir: &Path, path: &Path) -> String {
+    quote_bytes(path.strip_prefix(dir).unwrap_or(path).as_os_str().as_bytes())
+}
+
+// Track which paths are taken along the way, as applying the plan does
+fn update(layout: &mut Layout, operation: &Operation) {
+    match *operation {
+        Operation::Rename { ref from, ref to } => {
+            layout.vacate(from);
+            layout.occupy(to);
+        },
+        Operation::Copy { ref to, .. } | Operation::Hardlink { ref to, .. } => layout.occupy(to),
+        Operation::Symlink { ref path, .. } | Operation::CreateFile { ref path } |
+        Operation::CreateDir { ref path } => layout.occupy(path),
+        Operation::Remove { ref path } | Operation::RemoveDir { ref path } => layout.vacate(path),
+        Operation::Chmod { .. } | Operation::Chown { .. } | Operation::Touch { .. } => {}
+    }
+}
+
+// With a single directory, the script changes into it, or the directory given as
+// its first argument, and refers to entries inside of it by relative paths, so it
+// can be run elsewhere. Entries of several directories are referred to in full.
+pub fn write_script<W: Write>(w: &mut W, dirs: &[&Path], operations: &[Operation]) -> io::Result<()> {
+    try!(writeln!(w, "#!/bin/sh"));
+
+    let dir = if dirs.len() == 1 {
+        try!(writeln!(w, "# Edit directory {}\n", dirs[0].display()));
+        try!(writeln!(w, "set -e"));
+        try!(writeln!(w, "if [ $# -gt 0 ]; then cd -- \"$1\"; else cd -- {}; fi\n",
+                      quote_bytes(dirs[0].as_os_str().as_bytes())));
+        dirs[0]
+    } else {
+        let names: Vec<_> = dirs.iter().map(|dir| dir.to_string_lossy().into_owned()).collect();
+        try!(writeln!(w, "# Edit directories {}\n", names.join(", ")));
+        try!(writeln!(w, "set -e\n"));
+        Path::new("")
+    };
+
+    let mut layout = Layout::new();
+
+    for operation in operations {
+        let command = match *operation {
+            // Targets which exist at this point were allowed to be replaced by the plan
+            Operation::Rename { ref from, ref to } if layout.exists(to) =>
+                format!("mv -f -- {} {}", quote_path(dir, from), quote_path(dir, to)),
+            Operation::Rename { ref from, ref to } =>
+                format!("mv -n -- {} {}", quote_path(dir, from), quote_path(dir, to)),
+            Operation::Copy { ref from, ref to } =>
+                format!("cp -R -P -p -- {} {}", quote_path(dir, from), quote_path(dir, to)),
+            Operation::Symlink { ref target, ref path } =>
+                format!("ln -s -- {} {}", quote_bytes(target.as_os_str().as_bytes()), quote_path(dir, path)),
+            Operation::Hardlink { ref from, ref to } =>
+                format!("ln -- {} {}", quote_path(dir, from), quote_path(dir, to)),
+            Operation::Remove { ref path } =>
+                format!("rm -r -- {}", quote_path(dir, path)),
+            Operation::CreateFile { ref path } =>
+                format!(": > {}", quote_path(dir, path)),
+            Operation::Chmod { ref path, to, .. } =>
+                format!("chmod -- {} {}", attr::format_mode(to), quote_path(dir, path)),
+            Operation::Chown { ref path, to, .. } =>
+                format!("chown -h -- {} {}",
+                        quote(&format!("{}:{}", attr::user_name(to.0), attr::group_name(to.1))),
+                        quote_path(dir, path)),
+            Operation::Touch { ref path, to, .. } =>
+                format!("touch -m -d @{} -- {}", to, quote_path(dir, path)),
+            Operation::CreateDir { ref path } =>
+                format!("mkdir -p -- {}", quote_path(dir, path)),
+            Operation::RemoveDir { ref path } =>
+                format!("rmdir -- {}", quote_path(dir, path))
+        };
+
+        try!(writeln!(w, "{}", command));
+        update(&mut layout, operation);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr};
+    use std::fs::{create_dir, read_link, symlink_metadata, File};
+    use std::io::{Read, Write};
+    use std::os::unix::ffi::{OsStrExt};
+    use std::os::unix::fs::{MetadataExt};
+    use std::path::{Path};
+    use std::process::{Command};
+
+    use tempdir::{TempDir};
+
+    use plan::{Operation};
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!("'foo bar'", quote("foo bar"));
+        assert_eq!("'it'\\''s'", quote("it's"));
+        assert_eq!("'$(rm -rf ~)'", quote("$(rm -rf ~)"));
+        assert_eq!("''", quote_bytes(b""));
+        assert_eq!("'caf'\"$(printf '\\351')\"", quote_bytes(b"caf\xe9"));
+        assert_eq!("\"$(printf '\\377')\"'it'\\''s'", quote_bytes(b"\xffit's"));
+    }
+
+    #[test]
+    fn run() {
+        let tmp = TempDir::new("ded-script").unwrap();
+        let dir = tmp.path().join("dir");
+        let path = |name: &str| dir.join(name);
+
+        create_dir(&dir).unwrap();
+
+        for name in &["it's", "-n", "gone", "old", "taken"] {
+            File::create(path(name)).unwrap().write_all(name.as_bytes()).unwrap();
+        }
+
+        let raw = dir.join(OsStr::from_bytes(b"caf\xe9"));
+        File::create(&raw).unwrap();
+
+        let operations = vec![
+            Operation::Remove { path: path("gone") },
+            Operation::CreateDir { path: path("new dir") },
+            Operation::Rename { from: path("it's"), to: path("new dir/$HOME") },
+            Operation::Copy { from: path("-n"), to: path("new dir/\"copy\"") },
+            Operation::Symlink { target: Path::new("-n").to_path_buf(), path: path("link") },
+            Operation::CreateFile { path: path("empty") },
+            Operation::Chmod { path: path("-n"), from: 0o644, to: 0o600 },
+            Operation::Touch { path: path("-n"), from: 0, to: 1461146400 },
+            Operation::Rename { from: path("old"), to: path("taken") },
+            Operation::Rename { from: raw.clone(), to: path("cafe") },
+        ];
+
+        let script = tmp.path().join("script.sh");
+        write_script(&mut File::create(&script).unwrap(), &[&dir], &operations).unwrap();
+
+        // Run it from elsewhere, against the directory given as an argument
+        let status = Command::new("sh").arg(&script).arg(&dir).current_dir("/").status().unwrap();
+        assert!(status.success());
+
+        let mut contents = String::new();
+        File::open(path("new dir/$HOME")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!("it's", contents);
+
+        assert!(symlink_metadata(path("gone")).is_err());
+        assert!(symlink_metadata(path("new dir/\"copy\"")).is_ok());
+        assert_eq!(Path::new("-n"), read_link(path("link")).unwrap());
+        assert_eq!(0, symlink_metadata(path("empty")).unwrap().len());
+
+        let metadata = symlink_metadata(path("-n")).unwrap();
+        assert_eq!(0o600, metadata.mode() & 0o7777);
+        assert_eq!(1461146400, metadata.mtime());
+
+        // The existing target is replaced, as the plan allowed
+        contents.clear();
+        File::open(path("taken")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!("old", contents);
+
+        assert!(symlink_metadata(&raw).is_err());
+        assert!(symlink_metadata(path("cafe")).is_ok());
+    }
+
+    #[test]
+    fn several_dirs() {
+        let tmp = TempDir::new("ded-script").unwrap();
+        let (a, b) = (tmp.path().join("a"), tmp.path().join("b"));
+
+        create_dir(&a).unwrap();
+        create_dir(&b).unwrap();
+        File::create(a.join("x")).unwrap();
+
+        let operations = vec![Operation::Rename { from: a.join("x"), to: b.join("y") }];
+        let mut script = Vec::new();
+        write_script(&mut script, &[&a, &b], &operations).unwrap();
+
+        // Entries are referred to in full, so it runs from anywhere
+        let status = Command::new("sh").arg("-c").arg(String::from_utf8(script).unwrap())
+            .current_dir("/").status().unwrap();
+        assert!(status.success());
+        assert!(b.join("y").exists());
+    }
+}
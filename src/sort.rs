@@ -0,0 +1,299 @@
+use std::cmp::{Ordering};
+use std::collections::{HashMap};
+use std::fs::{symlink_metadata};
+use std::os::unix::fs::{MetadataExt};
+use std::path::{Path, PathBuf};
+
+use config::{Sort};
+use entry::{Entry};
+
+// `first' unless it is equal
+fn then(first: Ordering, second: Ordering) -> Ordering {
+    match first {
+        Ordering::Equal => second,
+        ordering => ordering
+    }
+}
+
+// Compares runs of digits by their numeric value, so `file2' goes before `file10'.
+// Names which differ only by leading zeros are ordered by the number of them.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    let mut zeros = Ordering::Equal;
+
+    loop {
+        let (a_digit, b_digit) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return then(zeros, a.cmp(b)),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => (a.is_digit(10), b.is_digit(10))
+        };
+
+        if !(a_digit && b_digit) {
+            match a_chars.next().cmp(&b_chars.next()) {
+                Ordering::Equal => continue,
+                ordering => return ordering
+            }
+        }
+
+        let mut a_run = String::new();
+        let mut b_run = String::new();
+
+        while a_chars.peek().map_or(false, |c| c.is_digit(10)) {
+            a_run.extend(a_chars.next());
+        }
+
+        while b_chars.peek().map_or(false, |c| c.is_digit(10)) {
+            b_run.extend(b_chars.next());
+        }
+
+        let a_number = a_run.trim_left_matches('0');
+        let b_number = b_run.trim_left_matches('0');
+
+        match (a_number.len(), a_number).cmp(&(b_number.len(), b_number)) {
+            Ordering::Equal => if zeros == Ordering::Equal {
+                zeros = a_run.len().cmp(&b_run.len());
+            },
+            ordering => return ordering
+        }
+    }
+}
+
+// Moves the contents of listed directories right after them, the order is kept otherwise
+fn parents_first(entries: &mut Vec<Entry>) {
+    let positions: HashMap<PathBuf, usize> = entries.iter().enumerate()
+        .map(|(index, entry)| (entry.path().to_path_buf(), index))
+        .collect();
+
+    // Positions of the entry and its listed parents, outermost first
+    let mut keyed: Vec<(Vec<usize>, Entry)> = entries.drain(..).map(|entry| {
+        let mut key = Vec::new();
+        let mut path = Some(entry.path());
+
+        while let Some(current) = path {
+            key.extend(positions.get(current));
+            path = current.parent();
+        }
+
+        key.reverse();
+        (key, entry)
+    }).collect();
+
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+
+    entries.extend(keyed.into_iter().map(|(_, entry)| entry));
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().map_or(String::new(), |ext| ext.to_string_lossy().to_lowercase())
+}
+
+// What entries are compared by, looked up once for each of them
+#[derive(Debug)]
+struct Key {
+    dir: bool,
+    hidden: bool,
+    mtime: i64,
+    size: u64
+}
+
+// How the listing is ordered in the transforms file, which is also the order
+// `%n' numbers entries in. Names and no key at all keep the contents of a
+// directory right after it, other keys order all entries as a single list.
+#[derive(Debug, Clone, Copy)]
+pub struct Order {
+    key: Sort,
+    reverse: bool,
+    dirs_first: bool,
+    hidden_first: bool
+}
+
+impl Order {
+    pub fn new(key: Sort) -> Self {
+        Order {
+            key: key,
+            reverse: false,
+            dirs_first: true,
+            hidden_first: true
+        }
+    }
+
+    // Only the key is reversed, directories and hidden entries still go first
+    pub fn reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    pub fn dirs_first(&mut self, dirs_first: bool) {
+        self.dirs_first = dirs_first;
+    }
+
+    pub fn hidden_first(&mut self, hidden_first: bool) {
+        self.hidden_first = hidden_first;
+    }
+
+    fn key(&self, entry: &Entry) -> Key {
+        let metadata = match self.key {
+            Sort::Mtime | Sort::Size => symlink_metadata(entry.path()).ok(),
+            _ => None
+        };
+
+        Key {
+            dir: entry.is_dir(),
+            hidden: entry.is_hidden(),
+            mtime: metadata.as_ref().map_or(0, |metadata| metadata.mtime()),
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len())
+        }
+    }
+
+    // Directories and hidden entries first, as far as they are enabled
+    fn group(&self, hidden: bool, dir: bool) -> (bool, bool) {
+        (self.hidden_first && !hidden, self.dirs_first && !dir)
+    }
+
+    fn cmp_names(&self, a: &Entry, b: &Entry) -> Ordering {
+        a.cmp_components(b, |(a, a_dir), (b, b_dir)| {
+            let ordering = if self.key == Sort::Natural { natural_cmp(a, b) } else { a.cmp(b) };
+            let ordering = if self.reverse { ordering.reverse() } else { ordering };
+
+            then(self.group(a.starts_with('.'), a_dir).cmp(&self.group(b.starts_with('.'), b_dir)), ordering)
+        })
+    }
+
+    fn cmp(&self, &(ref a_key, ref a): &(Key, Entry), &(ref b_key, ref b): &(Key, Entry)) -> Ordering {
+        let ordering = match self.key {
+            Sort::Name | Sort::Natural => return self.cmp_names(a, b),
+            Sort::Mtime => a_key.mtime.cmp(&b_key.mtime),
+            Sort::Size => a_key.size.cmp(&b_key.size),
+            Sort::Ext => extension(a.path()).cmp(&extension(b.path())),
+            // Entries are left in the order they were listed
+            Sort::None => Ordering::Equal
+        };
+
+        let ordering = if self.key == Sort::None {
+            ordering
+        } else {
+            then(ordering, a.name().cmp(b.name()))
+        };
+
+        then(self.group(a_key.hidden, a_key.dir).cmp(&self.group(b_key.hidden, b_key.dir)),
+             if self.reverse { ordering.reverse() } else { ordering })
+    }
+
+    pub fn sort(&self, entries: &mut Vec<Entry>) {
+        if self.key == Sort::None && self.reverse {
+            entries.reverse();
+        }
+
+        let mut keyed: Vec<(Key, Entry)> = entries.drain(..).map(|entry| (self.key(&entry), entry)).collect();
+        keyed.sort_by(|a, b| self.cmp(a, b));
+
+        entries.extend(keyed.into_iter().map(|(_, entry)| entry));
+
+        if self.key == Sort::None {
+            parents_first(entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::{Ordering};
+    use std::fs::{create_dir, File};
+    use std::io::{Write};
+
+    use tempdir::{TempDir};
+
+    use attr;
+    use config::{Sort};
+    use entry::{Entry};
+    use super::*;
+
+    #[test]
+    fn natural() {
+        let mut names = vec!["file10", "file2", "file02", "File1", "file1b", "file1", "10", "9a", "a"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(vec!["9a", "10", "File1", "a", "file1", "file1b", "file2", "file02", "file10"], names);
+
+        assert_eq!(Ordering::Less, natural_cmp("img_99.jpg", "img_100.jpg"));
+        assert_eq!(Ordering::Equal, natural_cmp("a1", "a1"));
+    }
+
+    #[test]
+    fn order() {
+        let tmp = TempDir::new("ded-sort").unwrap();
+        let dir = tmp.path();
+
+        for &(name, size, mtime) in &[("b.txt", 3, 300), ("a10.jpg", 1, 200), ("a9.png", 2, 100), (".h", 0, 400)] {
+            File::create(dir.join(name)).unwrap().write_all(&vec![0; size]).unwrap();
+            attr::set_mtime(&dir.join(name), mtime).unwrap();
+        }
+
+        create_dir(dir.join("d")).unwrap();
+        attr::set_mtime(&dir.join("d"), 500).unwrap();
+
+        let sorted = |order: &Order| {
+            let mut entries: Vec<_> = ["a10.jpg", "d", ".h", "b.txt", "a9.png"].iter()
+                .map(|name| Entry::relative(dir, dir.join(name)))
+                .collect();
+            order.sort(&mut entries);
+            entries.iter().map(|entry| String::from(entry.name())).collect::<Vec<_>>()
+        };
+
+        let mut order = Order::new(Sort::Name);
+        assert_eq!(vec![".h", "d/", "a10.jpg", "a9.png", "b.txt"], sorted(&order));
+
+        order = Order::new(Sort::Natural);
+        order.dirs_first(false);
+        assert_eq!(vec![".h", "a9.png", "a10.jpg", "b.txt", "d/"], sorted(&order));
+
+        order = Order::new(Sort::Mtime);
+        order.hidden_first(false);
+        assert_eq!(vec!["d/", "a9.png", "a10.jpg", "b.txt", ".h"], sorted(&order));
+
+        order.dirs_first(false);
+        order.reverse(true);
+        assert_eq!(vec!["d/", ".h", "b.txt", "a10.jpg", "a9.png"], sorted(&order));
+
+        order = Order::new(Sort::Size);
+        assert_eq!(vec![".h", "d/", "a10.jpg", "a9.png", "b.txt"], sorted(&order));
+
+        order = Order::new(Sort::Ext);
+        order.hidden_first(false);
+        order.dirs_first(false);
+        assert_eq!(vec![".h", "d/", "a10.jpg", "a9.png", "b.txt"], sorted(&order));
+
+        order = Order::new(Sort::None);
+        order.reverse(true);
+        assert_eq!(vec![".h", "d/", "a9.png", "b.txt", "a10.jpg"], sorted(&order));
+    }
+
+    #[test]
+    fn unsorted_tree() {
+        let tmp = TempDir::new("ded-sort").unwrap();
+        let dir = tmp.path();
+
+        create_dir(dir.join("d")).unwrap();
+
+        for name in &["d/x", "d/.h", "a"] {
+            File::create(dir.join(name)).unwrap();
+        }
+
+        let sorted = |order: &Order| {
+            let mut entries: Vec<_> = ["d", "d/x", "d/.h", "a"].iter()
+                .map(|name| Entry::relative(dir, dir.join(name)))
+                .collect();
+            order.sort(&mut entries);
+            entries.iter().map(|entry| String::from(entry.name())).collect::<Vec<_>>()
+        };
+
+        // Hidden entries go first among their siblings, not ahead of their directory
+        let mut order = Order::new(Sort::None);
+        assert_eq!(vec!["d/", "d/.h", "d/x", "a"], sorted(&order));
+
+        order.reverse(true);
+        order.hidden_first(false);
+        order.dirs_first(false);
+        assert_eq!(vec!["a", "d/", "d/.h", "d/x"], sorted(&order));
+    }
+}
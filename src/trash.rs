@@ -0,0 +1,243 @@
+use std::env;
+use std::ffi::{OsString};
+use std::fs::{create_dir_all, remove_file, rename, set_permissions, symlink_metadata, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use libc;
+use time;
+
+// Implementation of the freedesktop.org Trash specification:
+// https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+
+const STICKY_BIT: u32 = 0o1000;
+
+pub fn home_trash() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .and_then(|dir| if dir.is_empty() { None } else { Some(PathBuf::from(dir)) })
+        .or_else(|| env::home_dir().map(|home| home.join(".local").join("share")))
+        .map(|dir| dir.join("Trash"))
+}
+
+// Move `path` to the trash, recording `original` as the location to restore it to.
+// Returns the path of the trashed entry.
+pub fn put(path: &Path, original: &Path) -> io::Result<PathBuf> {
+    let home = try!(home_trash().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "cannot locate home trash directory")
+    }));
+
+    put_into(&home, path, original)
+}
+
+pub fn put_into(home: &Path, path: &Path, original: &Path) -> io::Result<PathBuf> {
+    let dev = try!(symlink_metadata(path)).dev();
+
+    if try!(device(home)) == dev {
+        return trash_entry(home, None, path, original);
+    }
+
+    let top = try!(topdir(path, dev));
+    let dir = try!(topdir_trash(&top));
+
+    trash_entry(&dir, Some(&top), path, original)
+}
+
+// Device of `path` or of its nearest existing ancestor
+fn device(path: &Path) -> io::Result<u64> {
+    let mut path = path;
+
+    loop {
+        match symlink_metadata(path) {
+            Ok(metadata) => return Ok(metadata.dev()),
+            Err(e) => match path.parent() {
+                Some(parent) => path = parent,
+                None => return Err(e)
+            }
+        }
+    }
+}
+
+// The mount point containing `path`
+fn topdir(path: &Path, dev: u64) -> io::Result<PathBuf> {
+    let mut top = path.to_path_buf();
+
+    while let Some(parent) = top.parent().map(Path::to_path_buf) {
+        if try!(symlink_metadata(&parent)).dev() != dev {
+            break;
+        }
+
+        top = parent;
+    }
+
+    Ok(top)
+}
+
+fn uid() -> u32 {
+    unsafe { libc::getuid() as u32 }
+}
+
+// Either `$topdir/.Trash/$uid`, when an administrator has set up a shared
+// trash directory, or `$topdir/.Trash-$uid`
+fn topdir_trash(top: &Path) -> io::Result<PathBuf> {
+    let shared = top.join(".Trash");
+
+    if let Ok(metadata) = symlink_metadata(&shared) {
+        let file_type = metadata.file_type();
+
+        if file_type.is_dir() && !file_type.is_symlink() && metadata.mode() & STICKY_BIT != 0 {
+            let dir = shared.join(uid().to_string());
+
+            if create_private_dir(&dir).is_ok() {
+                return Ok(dir);
+            }
+        }
+    }
+
+    let dir = top.join(format!(".Trash-{}", uid()));
+    try!(create_private_dir(&dir));
+    Ok(dir)
+}
+
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    if symlink_metadata(path).map(|m| m.is_dir()).unwrap_or(false) {
+        return Ok(());
+    }
+
+    try!(create_dir_all(path));
+    set_permissions(path, Permissions::from_mode(0o700))
+}
+
+fn trash_entry(dir: &Path, top: Option<&Path>, path: &Path, original: &Path) -> io::Result<PathBuf> {
+    let files = dir.join("files");
+    let info = dir.join("info");
+
+    try!(create_private_dir(&files));
+    try!(create_private_dir(&info));
+
+    let recorded = match top.map(|top| original.strip_prefix(top)) {
+        Some(Ok(relative)) => relative,
+        _ => original
+    };
+
+    let base = original.file_name().map_or_else(|| OsString::from("entry"), |name| name.to_os_string());
+    let mut counter = 1;
+
+    loop {
+        let mut name = base.clone();
+
+        if counter > 1 {
+            name.push(format!(".{}", counter));
+        }
+
+        let mut info_name = name.clone();
+        info_name.push(".trashinfo");
+        let info_path = info.join(info_name);
+
+        // The info file is created exclusively to reserve the name
+        let file = OpenOptions::new().write(true).create_new(true).open(&info_path);
+
+        let mut file = match file {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                counter += 1;
+                continue;
+            },
+            Err(e) => return Err(e)
+        };
+
+        let target = files.join(&name);
+
+        if symlink_metadata(&target).is_ok() {
+            try!(remove_file(&info_path));
+            counter += 1;
+            continue;
+        }
+
+        let result = write!(file, "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                            url_escape(recorded), deletion_date())
+            .and_then(|_| rename(path, &target));
+
+        return match result {
+            Ok(()) => Ok(target),
+            Err(e) => {
+                let _ = remove_file(&info_path);
+                Err(e)
+            }
+        };
+    }
+}
+
+fn deletion_date() -> String {
+    time::strftime("%Y-%m-%dT%H:%M:%S", &time::now()).unwrap()
+}
+
+fn url_escape(path: &Path) -> String {
+    let mut buf = String::new();
+
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+            b'-' | b'_' | b'.' | b'~' | b'/' => buf.push(b as char),
+            _ => buf.push_str(&format!("%{:02X}", b))
+        }
+    }
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, File};
+    use std::io::{Read};
+    use std::path::{Path};
+
+    use tempdir::{TempDir};
+
+    use super::*;
+
+    #[test]
+    fn escape() {
+        let path = Path::new("/tmp/foo bar/100%.txt");
+        assert_eq!("/tmp/foo%20bar/100%25.txt", url_escape(path));
+    }
+
+    #[test]
+    fn put_into_home() {
+        let tmp = TempDir::new("ded-trash").unwrap();
+        let home = tmp.path().join("Trash");
+        let original = tmp.path().join("foo");
+
+        create_dir(&original).unwrap();
+        let first = put_into(&home, &original, &original).unwrap();
+        File::create(&original).unwrap();
+        let second = put_into(&home, &original, &original).unwrap();
+
+        assert!(!original.exists());
+        assert_eq!(home.join("files").join("foo"), first);
+        assert_eq!(home.join("files").join("foo.2"), second);
+        assert!(first.is_dir());
+        assert!(second.is_file());
+
+        let mut info = String::new();
+        File::open(home.join("info").join("foo.2.trashinfo")).unwrap()
+            .read_to_string(&mut info).unwrap();
+
+        let lines: Vec<_> = info.lines().collect();
+        assert_eq!("[Trash Info]", lines[0]);
+        assert_eq!(format!("Path={}", url_escape(&original)), lines[1]);
+        assert!(lines[2].starts_with("DeletionDate="));
+    }
+
+    #[test]
+    fn topdir() {
+        let tmp = TempDir::new("ded-trash").unwrap();
+        let dev = device(tmp.path()).unwrap();
+        let top = super::topdir(tmp.path(), dev).unwrap();
+
+        assert!(tmp.path().starts_with(&top));
+        assert_eq!(dev, device(&top).unwrap());
+        assert!(top.parent().map_or(true, |parent| device(parent).unwrap() != dev));
+    }
+}
@@ -1,10 +1,11 @@
 use std::env;
-use std::fs;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 
 use crypto::digest::{Digest};
 use crypto::sha1::{Sha1};
+use libc;
 
 pub fn sha1(input: &str) -> String {
     let mut hasher = Sha1::new();
@@ -19,6 +20,25 @@ pub fn temp_dir<P: AsRef<Path>>(prefix: P) -> io::Result<PathBuf> {
     Ok(temp_dir)
 }
 
+// Directory for user configuration: $XDG_CONFIG_HOME/ded or ~/.config/ded
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("ded"))
+}
+
+// Expand a leading `~' to the home directory, `None' if it is unknown
+pub fn expand_home(path: &str) -> Option<PathBuf> {
+    if path == "~" {
+        env::home_dir()
+    } else if path.starts_with("~/") {
+        env::home_dir().map(|home| home.join(&path[2..]))
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
 pub fn width(n: usize) -> usize {
     (n as f64).log10().floor() as usize + 1
 }
@@ -29,6 +49,28 @@ pub fn get_editor() -> String {
         .unwrap_or_else(|_| String::from("vi"))
 }
 
+pub fn is_terminal(fd: libc::c_int) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+// A line from the controlling terminal, read a byte at a time so nothing after it is consumed
+pub fn read_tty_line(buf: &mut String) -> io::Result<usize> {
+    let tty = try!(File::open("/dev/tty"));
+    let mut line = Vec::new();
+
+    for byte in tty.bytes() {
+        let byte = try!(byte);
+        line.push(byte);
+
+        if byte == b'\n' {
+            break;
+        }
+    }
+
+    buf.push_str(&String::from_utf8_lossy(&line));
+    Ok(line.len())
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -39,4 +81,14 @@ mod tests {
         assert_eq!(2, super::width(99));
         assert_eq!(3, super::width(100));
     }
+
+    #[test]
+    fn expand_home() {
+        let home = ::std::env::home_dir().unwrap();
+
+        assert_eq!(Some(home.clone()), super::expand_home("~"));
+        assert_eq!(Some(home.join("templates/a")), super::expand_home("~/templates/a"));
+        assert_eq!(Some(::std::path::PathBuf::from("~user/a")), super::expand_home("~user/a"));
+        assert_eq!(Some(::std::path::PathBuf::from("a/~")), super::expand_home("a/~"));
+    }
 }